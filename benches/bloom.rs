@@ -1,8 +1,9 @@
 use bit_vec::BitVec;
 use bloom::BloomFilter;
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use rand::distributions::Uniform;
 use rand::Rng;
+use std::time::Instant;
 
 fn insert_item(b: &mut Criterion) {
     let mut f = BloomFilter::with_fp_size(0.05, 50000);
@@ -33,6 +34,84 @@ fn calc_hashes(b: &mut Criterion) {
     b.bench_function("compute_hashes", |b| b.iter(|| f.compute_hashes(&item)));
 }
 
+fn insert_32_byte_keys(b: &mut Criterion) {
+    let mut f = BloomFilter::with_fp_size(0.05, 50000);
+    let mut gen = rand::thread_rng();
+    let mut key = [0u8; 32];
+    for byte in key.iter_mut() {
+        *byte = gen.gen();
+    }
+
+    b.bench_function("add 32-byte key via Hash", |b| b.iter(|| f.add(&key[..])));
+    b.bench_function("add_bytes 32-byte key", |b| b.iter(|| f.add_bytes(&key)));
+}
+
+fn insert_u64_keys(b: &mut Criterion) {
+    let mut f = BloomFilter::with_fp_size(0.05, 50000);
+    let key: u64 = rand::thread_rng().gen();
+
+    b.bench_function("add u64 key via Hash", |b| b.iter(|| f.add(key)));
+    b.bench_function("add_u64 key", |b| b.iter(|| f.add_u64(key)));
+}
+
+fn get_absent_items(b: &mut Criterion) {
+    let mut f = BloomFilter::with_fp_size(0.01, 100_000);
+    for i in 0..100_000u32 {
+        f.add(i);
+    }
+    let mut gen = rand::thread_rng();
+    let absent = (0..100_000)
+        .map(|_| gen.sample(Uniform::new(100_000, u32::MAX)))
+        .collect::<Vec<u32>>();
+
+    // time one pass of each to report the speedup in the bench name
+    let start = Instant::now();
+    for i in absent.iter() {
+        black_box(f.matching_bits(i));
+    }
+    let full = start.elapsed();
+    let start = Instant::now();
+    for i in absent.iter() {
+        black_box(f.get(i));
+    }
+    let speedup = full.as_secs_f64() / start.elapsed().as_secs_f64();
+
+    // baseline: probing all k bits of every item, without allocating
+    b.bench_function("probe all k bits of 100k absent items", |b| {
+        b.iter(|| {
+            for i in absent.iter() {
+                black_box(f.matching_bits(i));
+            }
+        })
+    });
+    let name = format!(
+        "get 100k absent items (short-circuit, {:.1}x faster)",
+        speedup
+    );
+    b.bench_function(&name, |b| {
+        b.iter(|| {
+            for i in absent.iter() {
+                black_box(f.get(i));
+            }
+        })
+    });
+}
+
+fn current_fp(b: &mut Criterion) {
+    let mut f = BloomFilter::with_fp_size(0.05, 50000);
+    for i in 0..25000 {
+        f.add(i);
+    }
+
+    b.bench_function("fp 1k calls", |b| {
+        b.iter(|| {
+            for _ in 0..1000 {
+                black_box(f.current_fp());
+            }
+        })
+    });
+}
+
 fn insert_into_bitvec(b: &mut Criterion) {
     let mut v = BitVec::from_elem(16, false);
     b.bench_function("bitvec set raw", |b| {
@@ -49,6 +128,10 @@ criterion_group!(
     insert_item,
     insert_5k_items,
     calc_hashes,
+    insert_32_byte_keys,
+    insert_u64_keys,
+    get_absent_items,
+    current_fp,
     insert_into_bitvec
 );
 criterion_main!(benches);
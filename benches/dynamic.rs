@@ -1,5 +1,5 @@
 use bloom::dynamic::DynamicBloom;
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use rand::distributions::Uniform;
 use rand::Rng;
 
@@ -76,5 +76,48 @@ fn grow_buckets_and_look_up(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, insert_expected, grow_buckets_and_look_up);
+fn skewed_look_up(c: &mut Criterion) {
+    let mut group = c.benchmark_group("skewed_look_up");
+
+    let mut plain = DynamicBloom::new(1_000, 0.05);
+    let mut cached = DynamicBloom::new(1_000, 0.05).with_lookup_cache(16);
+    for i in 0..100_000u32 {
+        plain.add(i);
+        cached.add(i);
+    }
+    // 9 of 10 lookups go to hot items, the rest are spread evenly. Hot items
+    // are the latest inserts, found only after scanning every older
+    // partition, and a few absent ones, which the cache can't help with
+    let mut gen = rand::thread_rng();
+    let queries = (0..1_000)
+        .map(|i| match i % 10 {
+            0 => gen.sample(Uniform::new(0, 100_000u32)),
+            1 | 2 => gen.sample(Uniform::new(1_000_000, 1_000_016u32)),
+            _ => gen.sample(Uniform::new(99_984, 100_000u32)),
+        })
+        .collect::<Vec<u32>>();
+
+    group.bench_function("Get 1k skewed items without cache", |b| {
+        b.iter(|| {
+            for q in queries.iter() {
+                black_box(plain.get(q));
+            }
+        })
+    });
+    group.bench_function("Get 1k skewed items with 16 items cache", |b| {
+        b.iter(|| {
+            for q in queries.iter() {
+                black_box(cached.get(q));
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    insert_expected,
+    grow_buckets_and_look_up,
+    skewed_look_up
+);
 criterion_main!(benches);
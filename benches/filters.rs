@@ -0,0 +1,59 @@
+use bloom::const_bloom::ConstBloom;
+use bloom::dynamic::DynamicBloom;
+use bloom::fingerprint::FingerprintBloom;
+use bloom::{BloomFilter, Filter};
+use criterion::measurement::WallTime;
+use criterion::{
+    black_box, criterion_group, criterion_main, BenchmarkGroup, Criterion, Throughput,
+};
+
+const ITEMS: u64 = 10_000;
+
+/// Runs the same insert and lookup workload against any `Filter`
+fn workload<F: Filter, M: Fn() -> F>(group: &mut BenchmarkGroup<WallTime>, name: &str, make: M) {
+    group.bench_function(format!("{} insert", name), |b| {
+        b.iter(|| {
+            let mut f = make();
+            for i in 0..ITEMS {
+                f.add(i);
+            }
+            f
+        })
+    });
+
+    let mut f = make();
+    for i in 0..ITEMS {
+        f.add(i);
+    }
+    // half of the lookups are for absent items
+    group.bench_function(format!("{} lookup", name), |b| {
+        b.iter(|| {
+            for i in ITEMS / 2..ITEMS * 3 / 2 {
+                black_box(f.contains(i));
+            }
+        })
+    });
+}
+
+fn filters(c: &mut Criterion) {
+    let mut group = c.benchmark_group("Filter implementations");
+    group.throughput(Throughput::Elements(ITEMS));
+
+    workload(&mut group, "BloomFilter", || {
+        BloomFilter::with_fp_size(0.01, ITEMS)
+    });
+    workload(&mut group, "DynamicBloom", || {
+        DynamicBloom::new(1_000, 0.01)
+    });
+    // ~9.6 bits per item, as for fp 0.01
+    workload(&mut group, "ConstBloom", || {
+        ConstBloom::<12_000>::with_expected(ITEMS)
+    });
+    workload(&mut group, "FingerprintBloom", || {
+        FingerprintBloom::new(12_000, 7)
+    });
+    group.finish();
+}
+
+criterion_group!(benches, filters);
+criterion_main!(benches);
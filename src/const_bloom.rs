@@ -0,0 +1,152 @@
+/// Bloom filter with the storage size known at compile time, so the whole
+/// filter can live on the stack (e.g. for embedded use).
+/// Hashing is shared with `BloomFilter`.
+///
+use crate::BloomFilter;
+use crate::Filter;
+use crate::Hash;
+use crate::Reduction;
+
+pub struct ConstBloom<const BYTES: usize> {
+    // storage
+    array: [u8; BYTES],
+    // Number of passes for hash functions
+    k: usize,
+    // stored number of items
+    stored_items: u64,
+}
+
+impl<const BYTES: usize> ConstBloom<BYTES> {
+    /// Total bits count in filter
+    pub const BITS: usize = BYTES * 8;
+
+    /// Creates an empty filter with given k
+    pub const fn new(k: usize) -> Self {
+        assert!(BYTES > 0, "Filter should have non-zero size");
+        Self {
+            array: [0; BYTES],
+            k,
+            stored_items: 0,
+        }
+    }
+
+    /// Creates an empty filter with optimal k for `expected` number of items
+    pub const fn with_expected(expected: u64) -> Self {
+        Self::new(Self::optimal_k(expected))
+    }
+
+    /// Calculates optimal k value, k = ln(2) * m / n, at compile time
+    pub const fn optimal_k(expected: u64) -> usize {
+        assert!(expected != 0);
+        // ln(2) in fixed point, rounded to the nearest integer
+        let k = (Self::BITS as u64 * 693_147 + expected * 500_000) / (expected * 1_000_000);
+        if k == 0 {
+            1
+        } else {
+            k as usize
+        }
+    }
+
+    /// Returns number of currently stored items
+    pub fn stored(&self) -> u64 {
+        self.stored_items
+    }
+
+    /// Returns number of passes for hash functions
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// Returns i-th bit index for given base hashes
+    #[inline]
+    fn index(base: &[u128; 2], i: usize) -> usize {
+        Reduction::Modulo.position(base, i, Self::BITS as u128)
+    }
+
+    /// Adds item to filter
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut f = bloom::const_bloom::ConstBloom::<64>::with_expected(32);
+    /// f.add(&42);
+    /// assert!(f.get(&42));
+    /// ```
+    pub fn add<I: Hash>(&mut self, item: I) {
+        let base = BloomFilter::base_hashes(&item, [0, 0]);
+        for i in 0..self.k {
+            let idx = Self::index(&base, i);
+            self.array[idx / 8] |= 1 << (idx % 8);
+        }
+        self.stored_items += 1
+    }
+
+    /// Checks that item is in filter
+    pub fn get<I: Hash>(&self, item: I) -> bool {
+        let base = BloomFilter::base_hashes(&item, [0, 0]);
+        (0..self.k).all(|i| {
+            let idx = Self::index(&base, i);
+            self.array[idx / 8] & (1 << (idx % 8)) != 0
+        })
+    }
+}
+
+impl<const BYTES: usize> Filter for ConstBloom<BYTES> {
+    fn add<I: Hash>(&mut self, item: I) {
+        ConstBloom::add(self, item)
+    }
+
+    fn contains<I: Hash>(&self, item: I) -> bool {
+        self.get(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn insert_and_get_on_stack() {
+        let mut f = ConstBloom::<64>::with_expected(32);
+        assert_eq!(f.k(), 11);
+        for i in 0..32 {
+            f.add(i);
+        }
+        for i in 0..32 {
+            assert!(f.get(i));
+        }
+        assert_eq!(f.stored(), 32);
+        assert_eq!(std::mem::size_of_val(&f.array), 64);
+    }
+
+    #[test]
+    fn add_and_get_do_not_allocate() {
+        let mut f = ConstBloom::<64>::with_expected(32);
+        // the fasthash backend serializes items into a thread local buffer,
+        // which allocates on first use
+        BloomFilter::with_fp_size(0.1, 10).warm_up(16);
+        let before = crate::tests::allocations::count();
+        for i in 0..32 {
+            f.add(i);
+        }
+        assert!((0..32).all(|i| f.get(i)));
+        assert_eq!(crate::tests::allocations::count(), before);
+    }
+
+    #[test]
+    fn same_positions_as_bloom_filter() {
+        let f = ConstBloom::<64>::new(5);
+        let b = BloomFilter::new(64, 5, 0.1);
+        let base = BloomFilter::base_hashes(&42, [0, 0]);
+        let positions: Vec<usize> = (0..5).map(|i| ConstBloom::<64>::index(&base, i)).collect();
+        assert_eq!(positions, b.positions(42));
+        assert!(!f.get(42));
+    }
+
+    #[test]
+    fn optimal_k_is_const() {
+        const K: usize = ConstBloom::<128>::optimal_k(100);
+        assert_eq!(K, 7);
+    }
+}
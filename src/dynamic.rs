@@ -1,7 +1,33 @@
+use crate::{params, BloomError, BloomFilter, BloomStats, Filter, Hash, Reduction, WIDE_HASH_BITS};
 /// This file should contain implementation of http://www.greenorbs.org/people/liu/guodeke/dynamicbloomfilters.pdf
 ///
-use crate::BloomFilter;
-use crate::Hash;
+use bit_vec::BitVec;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Every new sub-filter gets its fp tightened by this ratio, so the aggregate
+// fp is bounded by the requested one: fp * (1 - r) * (1 + r + r^2 + ...) = fp
+const TIGHTENING_RATIO: f64 = 0.8;
+
+// Every new sub-filter is this many times larger than the previous one by
+// default, so its capacity keeps growing despite the tighter fp
+const GROWTH_FACTOR: f64 = 2.0;
+
+// An adaptive filter doubles the size of the next sub-filter when the active
+// one got full faster than this
+const FAST_FILL: Duration = Duration::from_secs(1);
+
+// Number of most recent resizes kept in fp history
+const FP_HISTORY_LEN: usize = 1024;
+
+// Above this fraction of set bits item estimates are too noisy for
+// `partition_overlap`
+const MAX_OVERLAP_FILL: f64 = 0.9;
+
+// Leading bytes of `to_bytes` output, the last one is the format version
+const MAGIC: &[u8; 4] = b"DBF\x01";
 
 pub struct DynamicBloom {
     filters: Vec<BloomFilter>,
@@ -9,20 +35,137 @@ pub struct DynamicBloom {
     expected: u64,
     fp: f64,
     inserted: u64,
+    // expected items of the next new sub-filter
+    partition_expected: u64,
+    // adaptive sizing doubles `partition_expected` only up to this, equals
+    // `expected` unless adaptive
+    max_expected: u64,
+    last_resize: Instant,
+    fast_fill: Duration,
+    // every new sub-filter expects this many times more items than the previous
+    growth_factor: f64,
+    // sub-filters scanned by the last `get`
+    last_probe_len: AtomicUsize,
+    // sum of sub-filters scanned and number of `get` calls
+    total_probe_len: AtomicU64,
+    lookups: AtomicU64,
+    // (inserted, aggregate fp) at recent resizes, oldest first
+    fp_history: Vec<(u64, f64)>,
+    // recently found items, see `with_lookup_cache`
+    cache: Option<Mutex<LookupCache>>,
+}
+
+/// Fixed size LRU of base hashes of items `get` has found. Bits are never
+/// cleared, so a found item stays found and entries never go stale.
+struct LookupCache {
+    capacity: usize,
+    // most recently used last
+    hashes: VecDeque<u128>,
+}
+
+impl LookupCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            hashes: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Checks for the hash, marking it as most recently used
+    fn contains(&mut self, hash: u128) -> bool {
+        match self.hashes.iter().rposition(|h| *h == hash) {
+            Some(idx) => {
+                self.hashes.remove(idx);
+                self.hashes.push_back(hash);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adds the hash, evicting the least recently used one if full
+    fn insert(&mut self, hash: u128) {
+        if self.hashes.len() == self.capacity {
+            self.hashes.pop_front();
+        }
+        self.hashes.push_back(hash);
+    }
 }
 
 impl DynamicBloom {
     pub fn new(expected: u64, fp: f64) -> Self {
-        let f = BloomFilter::with_fp_size(fp, expected);
-        let mut filters = Vec::new();
-        filters.push(f);
-        Self {
-            filters,
+        let mut f = Self {
+            filters: Vec::new(),
             active_idx: 0, // we start from first one
             expected,
             fp,
             inserted: 0,
-        }
+            partition_expected: expected,
+            max_expected: expected,
+            last_resize: Instant::now(),
+            fast_fill: FAST_FILL,
+            growth_factor: GROWTH_FACTOR,
+            last_probe_len: AtomicUsize::new(0),
+            total_probe_len: AtomicU64::new(0),
+            lookups: AtomicU64::new(0),
+            fp_history: Vec::new(),
+            cache: None,
+        };
+        let first = f.next_filter();
+        f.filters.push(first);
+        f
+    }
+
+    /// Creates a filter where every new sub-filter is `factor` times larger
+    /// than the previous one instead of 2 times, larger factors trade memory
+    /// for fewer sub-filters. The factor should make up for the fp tightening,
+    /// so sub-filters keep growing in capacity.
+    pub fn with_growth_factor(initial_expected: u64, fp: f64, factor: f64) -> Self {
+        assert!(
+            factor >= 1.0 / TIGHTENING_RATIO,
+            "Growth factor should be at least 1.25, so sub-filters outgrow fp tightening"
+        );
+        let mut f = DynamicBloom::new(initial_expected, fp);
+        f.growth_factor = factor;
+        f
+    }
+
+    /// Creates a filter which sizes new sub-filters by the insert rate: every
+    /// sub-filter that gets full in less than a second makes the next one twice
+    /// as large on top of the regular growth, while it expects fewer than
+    /// `max_expected` items, so bursts of inserts produce fewer, larger
+    /// sub-filters.
+    pub fn with_adaptive(initial_expected: u64, fp: f64, max_expected: u64) -> Self {
+        assert!(
+            max_expected >= initial_expected,
+            "Max expected should be at least initial expected"
+        );
+        let mut f = DynamicBloom::new(initial_expected, fp);
+        f.max_expected = max_expected;
+        f
+    }
+
+    /// Enables a cache of the `capacity` most recently found items, checked by
+    /// `get` before scanning sub-filters, e.g. for skewed lookups of a few hot
+    /// items. It's a linear scan, so keep it small.
+    pub fn with_lookup_cache(mut self, capacity: usize) -> Self {
+        assert!(capacity != 0, "Cache capacity should be non-zero");
+        self.cache = Some(Mutex::new(LookupCache::new(capacity)));
+        self
+    }
+
+    /// Returns target fp for sub-filter with given index
+    fn partition_fp(fp: f64, idx: usize) -> f64 {
+        fp * (1.0 - TIGHTENING_RATIO) * TIGHTENING_RATIO.powi(idx as i32)
+    }
+
+    /// Caps capacity of sub-filter `f` at index `idx` to the number of items
+    /// it holds within the fp target for that index
+    fn tighten(&self, f: &mut BloomFilter, idx: usize) {
+        let fp = DynamicBloom::partition_fp(self.fp, idx);
+        let capacity = BloomFilter::calculate_capacity_for_k(fp, f.size, f.k);
+        // at least one item, so a resize always makes progress
+        f.capacity = f.capacity.min(capacity).max(1);
     }
 
     /// Returns currently active filter
@@ -33,15 +176,90 @@ impl DynamicBloom {
             .expect("Index should be always valid")
     }
 
+    /// Creates a new sub-filter to be appended to `filters`.
+    ///
+    /// This is the scalable Bloom filter scheme: every sub-filter is
+    /// `growth_factor` times larger than the previous one and its fp is
+    /// tightened geometrically, so capacity still grows geometrically and the
+    /// number of sub-filters stays logarithmic in the number of items. All
+    /// sub-filters share k and their sizes are multiples of the first one,
+    /// sized for `expected` items, so bits of any of them fold onto the first
+    /// size (see `flatten`). The fp is tightened through capacity instead of
+    /// k: every sub-filter takes fewer items than it's sized for.
+    fn next_filter(&self) -> BloomFilter {
+        let plan = params::plan(self.fp, self.expected);
+        let scale = (self.partition_expected as f64 / self.expected as f64).ceil() as usize;
+        let mut f = BloomFilter::new(plan.size_bytes * scale, plan.k, self.fp);
+        self.tighten(&mut f, self.filters.len());
+        f
+    }
+
+    /// Grows the size of following sub-filters if the active one was filled
+    /// quickly
+    fn adapt_partition_size(&mut self) {
+        let now = Instant::now();
+        if now.duration_since(self.last_resize) < self.fast_fill {
+            let doubled = (self.partition_expected * 2).min(self.max_expected);
+            self.partition_expected = self.partition_expected.max(doubled);
+        }
+        self.last_resize = now;
+    }
+
     /// Should "resize", if the active filter has achieved its maximum capacity,
-    /// it will create a new filter and add it to `filters` and set it as `active`
+    /// it will switch to the next reserved filter, or create a new one and add it
+    /// to `filters`, and set it as `active`
     fn should_resize(&mut self) {
         let active = self.get_active();
         if active.stored() >= active.capacity() {
-            // add new filter
-            let f = BloomFilter::with_fp_size(self.fp, self.expected);
-            self.filters.push(f);
-            self.active_idx += 1;
+            self.record_resize(self.inserted, self.aggregate_fp());
+            if self.max_expected > self.expected {
+                self.adapt_partition_size();
+            }
+            // skips sub-filters merged by `extend` that are already full
+            loop {
+                if self.active_idx + 1 == self.filters.len() {
+                    self.push_partition();
+                }
+                self.active_idx += 1;
+                let active = self.get_active();
+                if active.stored() < active.capacity() {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Adds an entry to fp history, dropping the oldest one if full
+    fn record_resize(&mut self, inserted: u64, fp: f64) {
+        // resizes are rare, so shifting the history is cheap enough
+        if self.fp_history.len() == FP_HISTORY_LEN {
+            self.fp_history.remove(0);
+        }
+        self.fp_history.push((inserted, fp));
+    }
+
+    /// Appends a new empty sub-filter, grown by `growth_factor` from the
+    /// previous one
+    fn push_partition(&mut self) {
+        let grown = self.partition_expected as f64 * self.growth_factor;
+        self.partition_expected = grown.ceil() as u64;
+        let f = self.next_filter();
+        self.filters.push(f);
+    }
+
+    /// Pre-creates `num_filters` empty sub-filters, so the following resizes
+    /// reuse them instead of allocating in the middle of a stream of inserts.
+    /// Every reserved sub-filter gets the size and capacity a resize would
+    /// give it at its index, following `growth_factor`. Adaptive sizing only
+    /// applies to sub-filters created after the reserved ones, since their
+    /// size depends on how fast the reserved ones fill.
+    pub fn reserve(&mut self, num_filters: usize) {
+        self.filters.reserve(num_filters);
+        let history_len = self.fp_history.len() + num_filters;
+        self.fp_history
+            .reserve(history_len.min(FP_HISTORY_LEN) - self.fp_history.len());
+        for _ in 0..num_filters {
+            self.push_partition();
         }
     }
 
@@ -54,32 +272,431 @@ impl DynamicBloom {
         self.inserted += 1;
     }
 
-    pub fn get<I: Hash>(&mut self, item: I) -> bool {
-        for filter in self.filters.iter() {
-            if filter.get(&item) {
+    pub fn get<I: Hash>(&self, item: I) -> bool {
+        // sub-filters are unseeded, so they share base hashes
+        let base = BloomFilter::base_hashes(&item, [0, 0]);
+        if let Some(cache) = &self.cache {
+            if cache.lock().expect("Cache lock").contains(base[0]) {
+                self.record_probe_len(0);
                 return true;
             }
         }
-        return false;
+        let mut probe_len = self.filters.len();
+        let mut found = false;
+        for (idx, filter) in self.filters.iter().enumerate() {
+            if filter.check_positions(&base) {
+                probe_len = idx + 1;
+                found = true;
+                break;
+            }
+        }
+        if let (true, Some(cache)) = (found, &self.cache) {
+            cache.lock().expect("Cache lock").insert(base[0]);
+        }
+        self.record_probe_len(probe_len);
+        found
+    }
+
+    fn record_probe_len(&self, probe_len: usize) {
+        self.last_probe_len.store(probe_len, Ordering::Relaxed);
+        self.total_probe_len
+            .fetch_add(probe_len as u64, Ordering::Relaxed);
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Checks only the `n_partitions` most recently filled sub-filters, up to
+    /// the active one or the last non-empty one appended by `extend`, skipping
+    /// empty reserved ones. Items added before those partitions are reported
+    /// as absent, i.e. this has false negatives for older items.
+    pub fn contains_in_latest<I: Hash>(&self, item: I, n_partitions: usize) -> bool {
+        let filled = self.filters.iter().rposition(|f| f.stored() > 0);
+        let end = filled.map_or(0, |idx| idx + 1).max(self.active_idx + 1);
+        let start = end.saturating_sub(n_partitions);
+        self.filters[start..end].iter().any(|f| f.get(&item))
+    }
+
+    /// Returns how many sub-filters the last `get` scanned before finding the
+    /// item or giving up, `0` for a cache hit
+    pub fn last_probe_len(&self) -> usize {
+        self.last_probe_len.load(Ordering::Relaxed)
+    }
+
+    /// Returns average number of sub-filters scanned per `get`, high values
+    /// suggest merging sub-filters (see `freeze`)
+    pub fn avg_probe_len(&self) -> f64 {
+        let lookups = self.lookups.load(Ordering::Relaxed);
+        if lookups == 0 {
+            return 0.0;
+        }
+        self.total_probe_len.load(Ordering::Relaxed) as f64 / lookups as f64
     }
 
     pub fn len(&self) -> usize {
         self.filters.len()
     }
 
+    /// Returns all sub-filters, oldest first, e.g. for per-partition
+    /// diagnostics. Reserved sub-filters past the active one are empty.
+    pub fn filters(&self) -> &[BloomFilter] {
+        &self.filters
+    }
+
+    /// Returns occupancy stats of every sub-filter, e.g. to spot skew
+    pub fn partition_stats(&self) -> Vec<BloomStats> {
+        self.filters.iter().map(|f| f.stats()).collect()
+    }
+
+    /// Checks that the aggregate fp of all sub-filters is within the requested one
     pub fn assert_fp(&self) -> bool {
-        for (i, filter) in self.filters.iter().enumerate() {
-            if filter.fp() > self.fp {
-                return false;
+        self.aggregate_fp() <= self.fp
+    }
+
+    /// Returns fp of the whole filter, an item is a false positive if any
+    /// sub-filter reports it
+    fn aggregate_fp(&self) -> f64 {
+        let miss: f64 = self.filters.iter().map(|f| 1.0 - f.current_fp()).product();
+        1.0 - miss
+    }
+
+    /// Returns key metrics of all sub-filters as a compact JSON object, see
+    /// `BloomFilter::stats_json`
+    #[cfg(feature = "stats-json")]
+    pub fn stats_json(&self) -> String {
+        let stats = crate::StatsJson {
+            size: self.filters.iter().map(|f| f.size).sum(),
+            k: self.filters[self.active_idx].k,
+            items: self.inserted,
+            fp: self.aggregate_fp(),
+            partitions: self.filters.len(),
+        };
+        serde_json::to_string(&stats).expect("Stats should be serializable")
+    }
+
+    /// Returns `(inserted items, aggregate fp)` captured at every resize, for
+    /// the last 1024 resizes, oldest first
+    pub fn fp_history(&self) -> &[(u64, f64)] {
+        &self.fp_history
+    }
+
+    /// Serializes the filter: a header with sizing parameters, the active
+    /// sub-filter and number of inserted items, followed by every sub-filter's
+    /// size, k, capacity, stored items and bits. Integers are little endian.
+    /// The lookup cache, probe stats and fp history aren't persisted.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        let header = [
+            self.expected,
+            self.fp.to_bits(),
+            self.inserted,
+            self.active_idx as u64,
+            self.partition_expected,
+            self.max_expected,
+            self.growth_factor.to_bits(),
+            self.filters.len() as u64,
+        ];
+        for v in header.iter() {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        for f in &self.filters {
+            for v in [f.size as u64, f.k as u64, f.capacity, f.stored_items].iter() {
+                bytes.extend_from_slice(&v.to_le_bytes());
+            }
+            bytes.extend_from_slice(&f.array.to_bytes());
+        }
+        bytes
+    }
+
+    /// Restores a filter serialized with `to_bytes`, failing with
+    /// `InvalidEncoding` on truncated or inconsistent bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BloomError> {
+        let mut reader = ByteReader(bytes);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(BloomError::InvalidEncoding);
+        }
+        let expected = reader.u64()?;
+        let fp = f64::from_bits(reader.u64()?);
+        let inserted = reader.u64()?;
+        let active_idx = reader.u64()? as usize;
+        let partition_expected = reader.u64()?;
+        let max_expected = reader.u64()?;
+        let growth_factor = f64::from_bits(reader.u64()?);
+        let len = reader.u64()? as usize;
+        // NaNs fail both checks
+        let valid_fp = fp > 0.0 && fp < 1.0;
+        let valid_growth = growth_factor >= 1.0 / TIGHTENING_RATIO;
+        if expected == 0 || !valid_fp || !valid_growth || active_idx >= len {
+            return Err(BloomError::InvalidEncoding);
+        }
+
+        let mut f = DynamicBloom::new(expected, fp);
+        f.filters.clear();
+        for _ in 0..len {
+            let size = reader.u64()? as usize;
+            let k = reader.u64()? as usize;
+            let capacity = reader.u64()?;
+            let stored = reader.u64()?;
+            // checked before allocating, so a corrupt size can't exhaust memory
+            let bits = reader.take(size)?;
+            if size == 0 || k == 0 {
+                return Err(BloomError::InvalidEncoding);
+            }
+            let mut partition = BloomFilter::new(size, k, fp);
+            partition.capacity = capacity;
+            partition.stored_items = stored;
+            partition.array = BitVec::from_bytes(bits);
+            f.filters.push(partition);
+        }
+        if !reader.0.is_empty() {
+            return Err(BloomError::InvalidEncoding);
+        }
+        f.inserted = inserted;
+        f.active_idx = active_idx;
+        f.partition_expected = partition_expected;
+        f.max_expected = max_expected;
+        f.growth_factor = growth_factor;
+        Ok(f)
+    }
+
+    /// Estimates the average fraction of items shared between sub-filters.
+    ///
+    /// For every pair of sub-filters the intersection size is estimated from
+    /// the bits set in both of them and divided by the smaller of the two, so
+    /// `0.0` means disjoint partitions and `1.0` means one is fully contained
+    /// in the other. High overlap signals redundant merges (e.g. after `extend`).
+    ///
+    /// The larger sub-filter of a pair is folded onto the size of the smaller
+    /// one first (see `flatten`). Returns `None` if some pair can't be compared
+    /// that way: k differs, sizes aren't multiples, or the folded bits are so
+    /// full that item counts can't be estimated from them, which happens once
+    /// sub-filters grew many times larger than the first one.
+    pub fn partition_overlap(&self) -> Option<f64> {
+        let mut total = 0f64;
+        let mut pairs = 0;
+        for (i, a) in self.filters.iter().enumerate() {
+            for b in self.filters.iter().skip(i + 1) {
+                let (small, large) = if a.size <= b.size { (a, b) } else { (b, a) };
+                if small.k != large.k {
+                    return None;
+                }
+                let large = fold_onto(large, small.size)?;
+                let shared = small.shared_set_bits(&large);
+                let (small_set, large_set) = (small.set_bits(), large.set_bits());
+                let union_set = small_set + large_set - shared;
+                if union_set as f64 > MAX_OVERLAP_FILL * small.bits() as f64 {
+                    return None;
+                }
+                let n_small = small.estimate_items(small_set);
+                let n_large = small.estimate_items(large_set);
+                let n_union = small.estimate_items(union_set);
+                let smaller = n_small.min(n_large);
+                if smaller > 0.0 {
+                    total += ((n_small + n_large - n_union) / smaller).clamp(0.0, 1.0);
+                }
+                pairs += 1;
+            }
+        }
+        if pairs == 0 {
+            Some(0.0)
+        } else {
+            Some(total / pairs as f64)
+        }
+    }
+
+    /// Collapses all sub-filters into a single filter by OR-ing their bits.
+    ///
+    /// Sub-filters share k and their sizes are multiples of the first one, so
+    /// each of them folds onto the largest size all of them are multiples of:
+    /// with modulo reduction bit `i` of a sub-filter lands on bit `i % bits`,
+    /// right where the smaller filter would have put the item. Bits can't be
+    /// spread into a larger filter, so the flattened filter is no larger than
+    /// the smallest sub-filter while holding every item. Once that's more
+    /// items than its capacity, it would report nearly every key as present,
+    /// so this fails with `CapacityExceeded` instead, i.e. only filters which
+    /// barely grew past `expected` items can be flattened. Sub-filters past
+    /// 2^32 bits derive positions differently, so they can't be folded below
+    /// that and fail with `LengthMismatch`.
+    pub fn flatten(&self) -> Result<BloomFilter, BloomError> {
+        let size = self.filters.iter().map(|f| f.size).fold(0, gcd);
+        let mut flat = BloomFilter::new(size, self.filters[0].k, self.fp);
+        for f in &self.filters {
+            flat.stored_items += f.stored();
+            if flat.stored() > flat.capacity() {
+                return Err(BloomError::CapacityExceeded);
+            }
+            let folded = fold_onto(f, size).ok_or(BloomError::LengthMismatch)?;
+            flat.array.union(&folded.array);
+        }
+        Ok(flat)
+    }
+
+    /// Freezes the filter for lookups only. Sub-filters that together still
+    /// fit into the capacity of one of them (e.g. partially filled ones merged
+    /// with `extend`) are unioned, empty ones are dropped, and the rest are
+    /// packed into one contiguous bit array, so `contains` hashes an item once
+    /// and probes every sub-filter in a single allocation.
+    pub fn freeze(self) -> FrozenDynamicBloom {
+        let mut filters: Vec<BloomFilter> = Vec::new();
+        for f in self.filters.into_iter().filter(|f| f.stored() > 0) {
+            match filters
+                .iter_mut()
+                .find(|g| g.is_compatible_with(&f) && g.stored() + f.stored() <= g.capacity())
+            {
+                Some(g) => {
+                    g.union(&f);
+                }
+                None => filters.push(f),
             }
         }
-        true
+        let mut blocks = Vec::with_capacity(filters.iter().map(|f| f.array.storage().len()).sum());
+        let partitions = filters
+            .iter()
+            .map(|f| {
+                let partition = FrozenPartition {
+                    offset: blocks.len() * 32,
+                    bits: f.bits() as u128,
+                    k: f.k,
+                    reduction: f.reduction,
+                };
+                blocks.extend_from_slice(f.array.storage());
+                partition
+            })
+            .collect();
+        FrozenDynamicBloom { blocks, partitions }
     }
 
-    /// Extends (aka union) the filter from `other`, consuming `other`
+    /// Extends (aka union) the filter from `other`, consuming `other`.
+    ///
+    /// Sub-filters of `other` are appended after those of `self` and their
+    /// capacity is tightened for their new position, so they take no more
+    /// items than a sub-filter created there would. Items they already hold
+    /// keep their fp though, so the aggregate fp may exceed the target, up to
+    /// the sum of fps of both filters.
     pub fn extend(&mut self, other: Self) {
-        assert!(self.expected == other.expected, "Filters should be equal");
-        self.filters.extend(other.filters.into_iter())
+        assert!(
+            self.is_compatible_with(&other),
+            "Only filters with the same expected and fp can be extended"
+        );
+        for mut f in other.filters {
+            self.tighten(&mut f, self.filters.len());
+            self.filters.push(f);
+        }
+    }
+
+    /// Same as `extend`, but returns an error for incompatible filters instead
+    /// of panicking
+    pub fn try_extend(&mut self, other: Self) -> Result<(), BloomError> {
+        if !self.is_compatible_with(&other) {
+            return Err(BloomError::IncompatibleParameters);
+        }
+        self.extend(other);
+        Ok(())
+    }
+
+    /// Makes the sub-filter with the most remaining capacity active, e.g.
+    /// after `extend` pulled in partially filled sub-filters. Sub-filters keep
+    /// their order, so capacities tightened by position and the recency
+    /// `contains_in_latest` relies on stay intact, and once the new active one
+    /// is full, resizing moves on past it skipping full sub-filters.
+    pub fn rebalance(&mut self) {
+        let remaining = |f: &BloomFilter| f.capacity().saturating_sub(f.stored());
+        // the last of equally good ones, so later sub-filters are preferred
+        self.active_idx = (0..self.filters.len())
+            .max_by_key(|idx| remaining(&self.filters[*idx]))
+            .expect("There is always a sub-filter");
+    }
+
+    // sub-filters are sized from expected and fp alone, so those of either
+    // filter fit the other
+    fn is_compatible_with(&self, other: &Self) -> bool {
+        self.expected == other.expected && self.fp == other.fp
+    }
+}
+
+/// Read-only form of `DynamicBloom`, see `DynamicBloom::freeze`
+pub struct FrozenDynamicBloom {
+    // bits of all sub-filters back to back, each one starting a new block
+    blocks: Vec<u32>,
+    partitions: Vec<FrozenPartition>,
+}
+
+struct FrozenPartition {
+    // index of the first bit of the sub-filter in `blocks`
+    offset: usize,
+    bits: u128,
+    k: usize,
+    reduction: Reduction,
+}
+
+impl FrozenDynamicBloom {
+    /// Checks that item is in filter
+    pub fn contains<I: Hash>(&self, item: I) -> bool {
+        // sub-filters are unseeded, so they share base hashes
+        let base = BloomFilter::base_hashes(&item, [0, 0]);
+        self.partitions.iter().any(|p| {
+            (0..p.k).all(|i| {
+                let idx = p.offset + p.reduction.position(&base, i, p.bits);
+                self.blocks[idx / 32] & (1 << (idx % 32)) != 0
+            })
+        })
+    }
+
+    /// Returns number of sub-filters left after freezing
+    pub fn num_filters(&self) -> usize {
+        self.partitions.len()
+    }
+}
+
+/// Returns `f` with its bits OR-ed onto a filter of `size` bytes, see
+/// `DynamicBloom::flatten`, or `None` if its positions don't reduce to that size
+fn fold_onto(f: &BloomFilter, size: usize) -> Option<BloomFilter> {
+    let bits = size * 8;
+    let wide = f.bits() as u128 > WIDE_HASH_BITS && bits as u128 <= WIDE_HASH_BITS;
+    if !f.size.is_multiple_of(size) || f.reduction != Reduction::Modulo || wide {
+        return None;
+    }
+    let mut folded = BloomFilter::new(size, f.k, f.fp);
+    for idx in (0..f.bits()).filter(|idx| f.array[*idx]) {
+        folded.array.set(idx % bits, true);
+    }
+    folded.stored_items = f.stored();
+    Some(folded)
+}
+
+/// Reads `DynamicBloom::to_bytes` output front to back
+struct ByteReader<'a>(&'a [u8]);
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], BloomError> {
+        if self.0.len() < n {
+            return Err(BloomError::InvalidEncoding);
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u64(&mut self) -> Result<u64, BloomError> {
+        let mut v = [0u8; 8];
+        v.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(v))
+    }
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+impl Filter for DynamicBloom {
+    fn add<I: Hash>(&mut self, item: I) {
+        DynamicBloom::add(self, item)
+    }
+
+    fn contains<I: Hash>(&self, item: I) -> bool {
+        self.get(item)
     }
 }
 
@@ -119,6 +736,348 @@ mod tests {
         assert!(f.filters[1].get(77));
     }
 
+    #[test]
+    fn contains_in_latest() {
+        let mut f = DynamicBloom::new(16, 0.001);
+        let mut i = 0;
+        while f.len() < 3 {
+            f.add(i);
+            i += 1;
+        }
+        // the last item is the only one in the newest sub-filter
+        assert!(f.contains_in_latest(i - 1, 1));
+        assert!(!f.contains_in_latest(0, 1));
+        assert!(f.contains_in_latest(0, 3));
+        assert!(f.contains_in_latest(0, 10));
+        assert!(!f.contains_in_latest(i - 1, 0));
+    }
+
+    #[test]
+    fn contains_in_latest_after_extend() {
+        let mut a = DynamicBloom::new(100, 0.01);
+        let mut b = DynamicBloom::new(100, 0.01);
+        a.add(1);
+        b.add(2);
+        a.extend(b);
+        a.reserve(2);
+        assert!(a.get(2));
+        assert!(a.contains_in_latest(2, 1));
+        assert!(!a.contains_in_latest(1, 1));
+        assert!(a.contains_in_latest(1, a.len()));
+    }
+
+    #[test]
+    fn lookup_cache() {
+        let mut cached = DynamicBloom::new(100, 0.01).with_lookup_cache(8);
+        let mut plain = DynamicBloom::new(100, 0.01);
+        for i in 0..1000 {
+            cached.add(i);
+            plain.add(i);
+        }
+        for i in (0..2000).step_by(7) {
+            assert_eq!(cached.get(i), plain.get(i));
+            if i < 1000 {
+                // found items are served from the cache next time
+                assert!(cached.get(i));
+                assert_eq!(cached.last_probe_len(), 0);
+            }
+        }
+        // later inserts are found past cached items
+        cached.add(5000);
+        assert!(cached.get(5000));
+    }
+
+    #[test]
+    fn adaptive_burst_makes_fewer_partitions() {
+        let mut burst = DynamicBloom::with_adaptive(100, 0.05, 1600);
+        for i in 0..3000 {
+            burst.add(i);
+        }
+
+        let mut trickle = DynamicBloom::with_adaptive(100, 0.05, 1600);
+        trickle.fast_fill = Duration::from_millis(5);
+        for i in 0..3000 {
+            if i % 100 == 0 {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            trickle.add(i);
+        }
+
+        assert!(burst.len() < trickle.len());
+        // doubled on top of the regular growth
+        assert_eq!(burst.filters[1].bits(), 4 * burst.filters[0].bits());
+        for i in 0..3000 {
+            assert!(burst.get(i) && trickle.get(i));
+        }
+    }
+
+    #[test]
+    fn growth_factor_makes_fewer_partitions() {
+        let mut grown = DynamicBloom::with_growth_factor(100, 0.05, 4.0);
+        let mut doubled = DynamicBloom::new(100, 0.05);
+        for i in 0..10_000 {
+            grown.add(i);
+            doubled.add(i);
+        }
+        assert!(grown.len() < doubled.len());
+        assert!(grown
+            .filters
+            .windows(2)
+            .all(|w| w[1].bits() == 4 * w[0].bits()));
+        for i in 0..10_000 {
+            assert!(grown.get(i));
+        }
+        assert!(grown.assert_fp());
+    }
+
+    #[test]
+    fn partitions_stay_logarithmic() {
+        let mut f = DynamicBloom::new(1000, 0.05);
+        for i in 0..100_000 {
+            f.add(i);
+        }
+        // capacity grows by 2 * 0.8^(1/k) per partition
+        assert!(f.len() <= 10);
+        let bits: usize = f.filters.iter().map(|p| p.bits()).sum();
+        assert!(bits <= 3 * params::plan(0.05, 100_000).bits);
+        assert!(f.assert_fp());
+    }
+
+    #[test]
+    fn probe_len() {
+        let mut f = DynamicBloom::new(16, 0.001);
+        assert_eq!(f.avg_probe_len(), 0.0);
+        let mut i = 0;
+        while f.len() < 3 {
+            f.add(i);
+            i += 1;
+        }
+        // the last item is only in the last sub-filter
+        assert!(f.get(i - 1));
+        assert_eq!(f.last_probe_len(), 3);
+        assert!(f.get(0));
+        assert_eq!(f.last_probe_len(), 1);
+        assert_eq!(f.avg_probe_len(), 2.0);
+        assert!(!f.get(-1));
+        assert_eq!(f.last_probe_len(), 3);
+    }
+
+    #[test]
+    fn fp_history() {
+        let mut f = DynamicBloom::new(100, 0.05);
+        assert!(f.fp_history().is_empty());
+        for i in 0..1000 {
+            f.add(i);
+        }
+        let history = f.fp_history();
+        assert_eq!(history.len(), f.len() - 1);
+        assert!(history.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(history.windows(2).all(|w| w[0].1 <= w[1].1));
+        assert!(history.iter().all(|(_, fp)| *fp <= 0.05));
+    }
+
+    #[test]
+    fn fp_history_keeps_latest_resizes() {
+        let mut f = DynamicBloom::new(10, 0.05);
+        for i in 0..FP_HISTORY_LEN as u64 + 10 {
+            f.record_resize(i, 0.0);
+        }
+        let history = f.fp_history();
+        assert_eq!(history.len(), FP_HISTORY_LEN);
+        // the oldest 10 resizes were dropped
+        assert_eq!(history[0].0, 10);
+    }
+
+    #[test]
+    fn reserve_is_reused_on_resize() {
+        let mut f = DynamicBloom::with_growth_factor(16, 0.05, 2.0);
+        f.reserve(4);
+        assert_eq!(f.len(), 5);
+        // reserved partitions match those created on resize
+        let mut grown = DynamicBloom::with_growth_factor(16, 0.05, 2.0);
+        let mut i = 0;
+        while grown.len() < 5 {
+            grown.add(i);
+            i += 1;
+        }
+        for (r, g) in f.filters.iter().zip(&grown.filters) {
+            assert_eq!((r.bits(), r.capacity()), (g.bits(), g.capacity()));
+        }
+
+        // warms up the hashing scratch buffer
+        f.add(0);
+        let before = crate::tests::allocations::count();
+        // three resizes
+        let mut i = 1;
+        while f.active_idx < 3 {
+            f.add(i);
+            i += 1;
+        }
+        assert_eq!(crate::tests::allocations::count(), before);
+        assert_eq!(f.len(), 5);
+        assert!(f.filters[3].stored() > 0);
+        assert_eq!(f.filters[4].stored(), 0);
+        for j in 0..i {
+            assert!(f.get(j));
+        }
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let mut f = DynamicBloom::new(100, 0.01);
+        for i in 0..1000 {
+            f.add(i);
+        }
+        assert!(f.len() > 3);
+
+        let mut restored = DynamicBloom::from_bytes(&f.to_bytes()).unwrap();
+        assert_eq!(restored.len(), f.len());
+        assert_eq!(restored.active_idx, f.active_idx);
+        for i in 0..1000 {
+            assert!(restored.get(i));
+        }
+        for i in 1000..2000 {
+            assert_eq!(restored.get(i), f.get(i));
+        }
+        // keeps growing the same way
+        for i in 1000..5000 {
+            f.add(i);
+            restored.add(i);
+        }
+        assert_eq!(restored.to_bytes(), f.to_bytes());
+    }
+
+    #[test]
+    fn from_bytes_rejects_invalid_input() {
+        let mut f = DynamicBloom::new(100, 0.01);
+        f.add(1);
+        let bytes = f.to_bytes();
+        assert!(DynamicBloom::from_bytes(&bytes).is_ok());
+        for len in [0, 3, 20, bytes.len() - 1].iter() {
+            assert_eq!(
+                DynamicBloom::from_bytes(&bytes[..*len]).err(),
+                Some(BloomError::InvalidEncoding)
+            );
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(DynamicBloom::from_bytes(&trailing).is_err());
+        let mut version = bytes;
+        version[3] = 2;
+        assert!(DynamicBloom::from_bytes(&version).is_err());
+    }
+
+    #[test]
+    fn partition_stats() {
+        let mut f = DynamicBloom::new(16, 0.05);
+        for i in 0..40 {
+            f.add(i);
+        }
+        let stats = f.partition_stats();
+        assert_eq!(stats.len(), f.len());
+        assert!(stats.len() > 1);
+        assert_eq!(stats.iter().map(|s| s.stored_items).sum::<u64>(), 40);
+        assert!(stats.iter().all(|s| s.fill_ratio > 0.0));
+    }
+
+    #[test]
+    #[cfg(feature = "stats-json")]
+    fn stats_json() {
+        let mut f = DynamicBloom::new(100, 0.01);
+        for i in 0..1000 {
+            f.add(i);
+        }
+        let json: serde_json::Value = serde_json::from_str(&f.stats_json()).unwrap();
+        let keys: Vec<&String> = json.as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["fp", "items", "k", "partitions", "size"]);
+        assert_eq!(json["items"].as_u64(), Some(1000));
+        assert_eq!(json["partitions"].as_u64(), Some(f.len() as u64));
+        assert!(json["fp"].as_f64().unwrap() <= 0.01);
+    }
+
+    #[test]
+    fn filters() {
+        let mut f = DynamicBloom::new(100, 0.01);
+        for i in 0..500 {
+            f.add(i);
+        }
+        assert_eq!(f.filters().len(), f.len());
+        assert!(f.filters().len() > 1);
+        // every item is in one of the sub-filters
+        for i in 0..500 {
+            assert!(f.filters().iter().any(|p| p.get(i)));
+        }
+        assert!(f.filters()[0].get(0));
+    }
+
+    #[test]
+    fn aggregate_fp_stays_under_target() {
+        let mut f = DynamicBloom::new(1000, 0.05);
+        for i in 0..20_000 {
+            f.add(i);
+        }
+        assert!(f.len() >= 5);
+        assert!(f.assert_fp());
+
+        let probes = 20_000;
+        let positives = (1_000_000..1_000_000 + probes).filter(|i| f.get(i)).count();
+        assert!((positives as f64 / probes as f64) < 0.05);
+    }
+
+    #[test]
+    fn partition_overlap_of_disjoint_partitions() {
+        let mut f = DynamicBloom::new(1000, 0.01);
+        for i in 0..3000 {
+            f.add(i);
+        }
+        assert!(f.len() >= 3);
+        assert!(f.partition_overlap().unwrap() < 0.05);
+    }
+
+    #[test]
+    fn partition_overlap_across_sizes() {
+        let mut a = DynamicBloom::new(1000, 0.01);
+        for i in 0..1000 {
+            a.add(i);
+        }
+        assert_eq!(a.len(), 2);
+        assert!(a.filters[1].bits() > a.filters[0].bits());
+        assert!(a.partition_overlap().unwrap() < 0.05);
+
+        // the same items as the larger partition of `a`
+        let first = a.filters[0].stored() as i32;
+        let mut b = DynamicBloom::new(1000, 0.01);
+        for i in first..1000 {
+            b.add(i);
+        }
+        assert_eq!(b.len(), 1);
+        a.extend(b);
+        // one of three pairs is fully shared
+        let overlap = a.partition_overlap().unwrap();
+        assert!(overlap > 0.25 && overlap < 0.4);
+    }
+
+    #[test]
+    fn partition_overlap_of_saturated_folds() {
+        let mut f = DynamicBloom::new(100, 0.01);
+        for i in 0..2000 {
+            f.add(i);
+        }
+        assert_eq!(f.partition_overlap(), None);
+    }
+
+    #[test]
+    fn partition_overlap_of_same_partitions() {
+        let mut a = DynamicBloom::new(1000, 0.01);
+        let mut b = DynamicBloom::new(1000, 0.01);
+        for i in 0..500 {
+            a.add(i);
+            b.add(i);
+        }
+        a.extend(b);
+        assert!(a.partition_overlap().unwrap() > 0.9);
+    }
+
     #[test]
     fn extend() {
         let mut a = DynamicBloom::new(16, 0.05);
@@ -137,6 +1096,144 @@ mod tests {
         assert!(a.get(31));
     }
 
+    #[test]
+    fn flatten() {
+        let mut a = DynamicBloom::new(100, 0.01);
+        for n in 0..3 {
+            let mut b = DynamicBloom::new(100, 0.01);
+            for i in 0..25 {
+                b.add(n * 100 + i);
+            }
+            a.extend(b);
+        }
+        assert_eq!(a.len(), 4);
+
+        let f = a.flatten().unwrap();
+        assert_eq!(f.stored(), 75);
+        for n in 0..3 {
+            for i in 0..25 {
+                assert!(f.get(n * 100 + i));
+            }
+        }
+    }
+
+    #[test]
+    fn flatten_after_resize() {
+        let mut f = DynamicBloom::new(100, 0.01);
+        for i in 0..90 {
+            f.add(i);
+        }
+        assert_eq!(f.len(), 2);
+
+        let flat = f.flatten().unwrap();
+        assert_eq!(flat.bits(), f.filters[0].bits());
+        assert_eq!(flat.stored(), 90);
+        for i in 0..90 {
+            assert!(flat.get(i));
+        }
+        let probes = 20_000;
+        let positives = (1_000_000..1_000_000 + probes)
+            .filter(|i| flat.get(i))
+            .count();
+        assert!((positives as f64 / probes as f64) < 0.02);
+    }
+
+    #[test]
+    fn flatten_fails_when_overfull() {
+        let mut f = DynamicBloom::new(100, 0.01);
+        for i in 0..2000 {
+            f.add(i);
+        }
+        assert_eq!(f.flatten().err(), Some(BloomError::CapacityExceeded));
+    }
+
+    #[test]
+    fn freeze() {
+        let mut a = DynamicBloom::new(100, 0.01);
+        for i in 1000..1300 {
+            a.add(i);
+        }
+        for n in 0..3 {
+            let mut b = DynamicBloom::new(100, 0.01);
+            for i in 0..20 {
+                b.add(n * 100 + i);
+            }
+            a.extend(b);
+        }
+        let len = a.len();
+        let inserted: Vec<i32> = (0..3)
+            .flat_map(|n| (0..20).map(move |i| n * 100 + i))
+            .chain(1000..1300)
+            .collect();
+        assert!(inserted.iter().all(|i| a.get(i)));
+
+        // the partially filled partitions merged with `extend` fit into one
+        let frozen = a.freeze();
+        assert!(frozen.num_filters() < len);
+        assert!(inserted.iter().all(|i| frozen.contains(i)));
+    }
+
+    #[test]
+    fn freeze_grown_filter() {
+        let mut f = DynamicBloom::with_growth_factor(100, 0.01, 2.0);
+        for i in 0..5000 {
+            f.add(i);
+        }
+        assert!(f.len() > 3);
+        let found: Vec<bool> = (0..10_000).map(|i| f.get(i)).collect();
+
+        let frozen = f.freeze();
+        assert!((0..10_000).all(|i| frozen.contains(i) == found[i as usize]));
+    }
+
+    #[test]
+    fn try_extend_errors() {
+        let mut a = DynamicBloom::new(16, 0.05);
+        assert_eq!(
+            a.try_extend(DynamicBloom::new(14, 0.05)),
+            Err(BloomError::IncompatibleParameters)
+        );
+        assert_eq!(
+            a.try_extend(DynamicBloom::new(16, 0.01)),
+            Err(BloomError::IncompatibleParameters)
+        );
+        assert!(a.try_extend(DynamicBloom::new(16, 0.05)).is_ok());
+        assert_eq!(a.len(), 2);
+    }
+
+    #[test]
+    fn rebalance() {
+        let mut a = DynamicBloom::new(100, 0.05);
+        for i in 0..250 {
+            a.add(i);
+        }
+        let mut b = DynamicBloom::new(100, 0.05);
+        for i in 1000..1010 {
+            b.add(i);
+        }
+        a.extend(b);
+        let stored: Vec<u64> = a.filters.iter().map(|f| f.stored()).collect();
+        a.rebalance();
+
+        // order is kept
+        assert_eq!(
+            a.filters.iter().map(|f| f.stored()).collect::<Vec<u64>>(),
+            stored
+        );
+        let remaining: Vec<u64> = a
+            .filters
+            .iter()
+            .map(|f| f.capacity().saturating_sub(f.stored()))
+            .collect();
+        let max = *remaining.iter().max().unwrap();
+        assert_eq!(remaining[a.active_idx], max);
+        for i in (0..250).chain(1000..1010) {
+            assert!(a.get(i));
+        }
+        a.add(5000);
+        assert!(a.filters[a.active_idx].get(5000));
+    }
+
     #[test]
     #[should_panic]
     fn extend_panics_when_filters_are_different() {
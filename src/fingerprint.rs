@@ -0,0 +1,120 @@
+/// Bloom filter keeping a short fingerprint mask next to every bit: each of
+/// the `m` cells records which of 8 fingerprints the items hitting it had, and
+/// a lookup needs the item's fingerprint in all of its `k` cells, not just
+/// set cells. That costs 8 bits per cell and cuts fp well below the one of a
+/// `BloomFilter` with `m` bits.
+///
+use crate::BloomFilter;
+use crate::Filter;
+use crate::Hash;
+
+pub struct FingerprintBloom {
+    // fingerprint mask of every cell, zero for an unset cell
+    cells: Vec<u8>,
+    // Number of passes for hash functions
+    k: usize,
+    // stored number of items
+    stored_items: u64,
+}
+
+impl FingerprintBloom {
+    /// Creates an empty filter with as many cells as a `BloomFilter` of given
+    /// size in _bytes_ has bits
+    pub fn new(size: usize, k: usize) -> Self {
+        assert!(size > 0, "Filter should have non-zero size");
+        Self {
+            cells: vec![0; size * 8],
+            k,
+            stored_items: 0,
+        }
+    }
+
+    /// Returns number of currently stored items
+    pub fn stored(&self) -> u64 {
+        self.stored_items
+    }
+
+    /// Returns number of passes for hash functions
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// Returns number of bytes taken by cells
+    pub fn heap_bytes(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// Returns cell index and fingerprint bit for the i-th hash. The index
+    /// comes from the low bits as in `BloomFilter`, the fingerprint from the
+    /// top 3 bits.
+    #[inline]
+    fn cell(&self, base: &[u128; 2], i: usize) -> (usize, u8) {
+        let hash = BloomFilter::nth_hash(base, i);
+        let idx = (hash % self.cells.len() as u128) as usize;
+        (idx, 1 << (hash >> 125) as u8)
+    }
+
+    /// Adds item to filter
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut f = bloom::fingerprint::FingerprintBloom::new(64, 4);
+    /// f.add(&42);
+    /// assert!(f.contains(&42));
+    /// ```
+    pub fn add<I: Hash>(&mut self, item: I) {
+        let base = BloomFilter::base_hashes(&item, [0, 0]);
+        for i in 0..self.k {
+            let (idx, fingerprint) = self.cell(&base, i);
+            self.cells[idx] |= fingerprint;
+        }
+        self.stored_items += 1
+    }
+
+    /// Checks that item is in filter
+    pub fn contains<I: Hash>(&self, item: I) -> bool {
+        let base = BloomFilter::base_hashes(&item, [0, 0]);
+        (0..self.k).all(|i| {
+            let (idx, fingerprint) = self.cell(&base, i);
+            self.cells[idx] & fingerprint != 0
+        })
+    }
+}
+
+impl Filter for FingerprintBloom {
+    fn add<I: Hash>(&mut self, item: I) {
+        FingerprintBloom::add(self, item)
+    }
+
+    fn contains<I: Hash>(&self, item: I) -> bool {
+        FingerprintBloom::contains(self, item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn lower_fp_than_plain_filter() {
+        let mut plain = BloomFilter::with_fp_size(0.05, 1000);
+        let mut f = FingerprintBloom::new(plain.bits() / 8, plain.k);
+        for i in 0..1000 {
+            plain.add(i);
+            f.add(i);
+        }
+        assert_eq!(f.stored(), 1000);
+        for i in 0..1000 {
+            assert!(f.contains(i));
+        }
+
+        let probes = 20_000;
+        let absent = 1_000_000..1_000_000 + probes;
+        let plain_fp = absent.clone().filter(|i| plain.get(i)).count();
+        let fp = absent.filter(|i| f.contains(i)).count();
+        assert!(plain_fp > probes as usize / 100);
+        assert!(fp * 4 < plain_fp);
+    }
+}
@@ -1,13 +1,366 @@
 use bit_vec::BitVec;
+#[cfg(feature = "fasthash")]
 use fasthash::{FastHasher, HasherExt, Murmur3HasherExt, SpookyHasherExt, XXH3HasherExt};
+#[cfg(feature = "stats-json")]
+use serde::Serialize;
+#[cfg(all(
+    feature = "fasthash",
+    not(any(feature = "ahash", feature = "portable-hash"))
+))]
+use std::cell::RefCell;
+#[cfg(feature = "provenance")]
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::error::Error;
 use std::f64::consts::E;
-use std::hash::Hash;
+use std::fmt;
+#[cfg(all(feature = "ahash", not(feature = "portable-hash")))]
+use std::hash::BuildHasher;
+use std::hash::{Hash, Hasher};
+use std::ops::{BitAnd, BitOr, Range};
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "zeroize")]
+use zeroize::Zeroize;
 
+pub mod const_bloom;
 pub mod dynamic;
+pub mod fingerprint;
+pub mod params;
+#[cfg(feature = "portable-hash")]
+mod sip;
+pub mod sparse;
+
+#[cfg(not(any(feature = "fasthash", feature = "ahash", feature = "portable-hash")))]
+compile_error!("one of `fasthash`, `ahash` or `portable-hash` features should be enabled");
+
+// fixed ahash seeds for the four 64-bit halves of the base hashes
+#[cfg(all(feature = "ahash", not(feature = "portable-hash")))]
+const AHASH_SEEDS: [[u64; 4]; 4] = [
+    [
+        0x243f6a8885a308d3,
+        0x13198a2e03707344,
+        0xa4093822299f31d0,
+        0x082efa98ec4e6c89,
+    ],
+    [
+        0x452821e638d01377,
+        0xbe5466cf34e90c6c,
+        0xc0ac29b7c97c50dd,
+        0x3f84d5b5b5470917,
+    ],
+    [
+        0x9216d5d98979fb1b,
+        0xd1310ba698dfb5ac,
+        0x2ffd72dbd01adfb7,
+        0xb8e1afed6a267e96,
+    ],
+    [
+        0xba7c9045f12c7f99,
+        0x24a19947b3916cf7,
+        0x0801f2e2858efc16,
+        0x636920d871574e69,
+    ],
+];
 
 // the highest prime that fits into u64
 const HASH_PRIME: u64 = 0xffffffffffffffc5;
 
+// filters up to this many bits derive positions with the 64-bit recurrence,
+// larger ones run it in 128-bit space, see `BloomFilter::nth_hash`
+const WIDE_HASH_BITS: u128 = 1 << 32;
+
+// fixed SipHash keys for the two portable base hashes
+#[cfg(feature = "portable-hash")]
+const SIP_KEYS: [(u64, u64); 2] = [
+    (0x0706050403020100, 0x0f0e0d0c0b0a0908),
+    (0x1716151413121110, 0x1f1e1d1c1b1a1918),
+];
+
+#[cfg(all(
+    feature = "fasthash",
+    not(any(feature = "ahash", feature = "portable-hash"))
+))]
+thread_local! {
+    // bytes an item writes into a hasher, serialized once for both fasthash
+    // base hashes and reused between calls, so hashing allocates only while
+    // the buffer grows to the largest item seen on the thread
+    static HASH_SCRATCH: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+// Largest hashing buffer kept between calls, a larger one is freed right away
+// so a single huge item doesn't pin its memory for the thread's lifetime
+#[cfg(all(
+    feature = "fasthash",
+    not(any(feature = "ahash", feature = "portable-hash"))
+))]
+const MAX_SCRATCH_BYTES: usize = 64 * 1024;
+
+/// Collects what an item writes into a hasher, so `Hash` output can be fed to
+/// the one-shot fasthash functions. Only `write` is implemented, the other
+/// `write_*` defaults produce the same bytes fasthash hashers would get.
+#[cfg(all(
+    feature = "fasthash",
+    not(any(feature = "ahash", feature = "portable-hash"))
+))]
+struct ByteSink<'a>(&'a mut Vec<u8>);
+
+#[cfg(all(
+    feature = "fasthash",
+    not(any(feature = "ahash", feature = "portable-hash"))
+))]
+impl Hasher for ByteSink<'_> {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        unreachable!("bytes are hashed by the caller")
+    }
+}
+
+/// Common interface of all filters, for code generic over the filter type
+pub trait Filter {
+    /// Adds item to filter
+    fn add<I: Hash>(&mut self, item: I);
+    /// Checks that item is in filter
+    fn contains<I: Hash>(&self, item: I) -> bool;
+}
+
+/// Returned when an item is inserted into a filter that already holds
+/// `capacity` items
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapacityExceeded;
+
+impl fmt::Display for CapacityExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "filter capacity exceeded")
+    }
+}
+
+impl Error for CapacityExceeded {}
+
+/// Errors returned by fallible filter operations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BloomError {
+    /// Size of storage is zero
+    ZeroSize,
+    /// Given parameters can't hold a single item
+    ZeroCapacity,
+    /// False probability is not within (0, 1)
+    InvalidFp,
+    /// Filters differ in size, k or fp and can't be combined
+    IncompatibleParameters,
+    /// Bit lengths don't line up for a fold or merge
+    LengthMismatch,
+    /// Operation needs at least one filter
+    NoFilters,
+    /// Storage of requested size can't be allocated
+    AllocFailed,
+    /// Items map to too few distinct bits, see `BloomFilter::self_check`
+    DegenerateHashing,
+    /// Result would hold more items than its capacity
+    CapacityExceeded,
+    /// Bytes are truncated or don't encode a filter
+    InvalidEncoding,
+}
+
+impl fmt::Display for BloomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BloomError::ZeroSize => write!(f, "filter size should be non-zero"),
+            BloomError::ZeroCapacity => {
+                write!(f, "given parameters is too small to create a filter")
+            }
+            BloomError::InvalidFp => write!(f, "false probability should be within (0, 1)"),
+            BloomError::IncompatibleParameters => {
+                write!(f, "filters have different size, k or fp")
+            }
+            BloomError::LengthMismatch => write!(f, "filter bit lengths don't line up"),
+            BloomError::NoFilters => write!(f, "no filters given"),
+            BloomError::AllocFailed => write!(f, "filter storage can't be allocated"),
+            BloomError::DegenerateHashing => write!(f, "items map to too few distinct bits"),
+            BloomError::CapacityExceeded => write!(f, "filter capacity exceeded"),
+            BloomError::InvalidEncoding => write!(f, "bytes don't encode a filter"),
+        }
+    }
+}
+
+impl Error for BloomError {}
+
+/// Returned by `BloomFilter::self_check` when items map to fewer distinct
+/// bits than expected, i.e. hashing is degenerate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SelfCheckError {
+    /// Average number of distinct bits per probe item
+    pub effective_k: f64,
+    /// Expected average number of distinct bits per item
+    pub expected_k: f64,
+}
+
+impl fmt::Display for SelfCheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "items map to {:.2} distinct bits on average, expected {:.2}",
+            self.effective_k, self.expected_k
+        )
+    }
+}
+
+impl Error for SelfCheckError {}
+
+/// Returned by `BloomFilter::validate` when the recorded parameters don't
+/// match each other
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValidationError {
+    /// `capacity` differs from the one computed from size and fp
+    CapacityMismatch {
+        /// Recorded capacity
+        recorded: u64,
+        /// Capacity computed from size and fp
+        computed: u64,
+    },
+    /// With the recorded k, fp at full capacity is worse than the target
+    FpMismatch {
+        /// Configured fp
+        target: f64,
+        /// fp computed from size, k and capacity
+        computed: f64,
+    },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::CapacityMismatch { recorded, computed } => write!(
+                f,
+                "capacity is {}, but size and fp give {}",
+                recorded, computed
+            ),
+            ValidationError::FpMismatch { target, computed } => write!(
+                f,
+                "fp at capacity is {:.3e}, target is {:.3e}",
+                computed, target
+            ),
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+/// Snapshot of filter occupancy
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BloomStats {
+    /// Number of stored items
+    pub stored_items: u64,
+    /// Fraction of bits set
+    pub fill_ratio: f64,
+    /// Current fp rate
+    pub fp: f64,
+}
+
+/// How a hash is reduced to a bit index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Reduction {
+    /// `hash % m`, the original layout
+    #[default]
+    Modulo,
+    /// `(hash * m) >> 64` of a 64-bit hash (of the high 64 bits of a
+    /// 128-bit one), which has no bias towards low indices when `m` isn't a
+    /// power of two
+    MultiplyShift,
+}
+
+impl Reduction {
+    #[inline]
+    fn index(self, hash: u128, bits: u128) -> usize {
+        match self {
+            Reduction::Modulo => (hash % bits) as usize,
+            Reduction::MultiplyShift => (((hash >> 64) * bits) >> 64) as usize,
+        }
+    }
+
+    #[inline]
+    fn index_64(self, hash: u64, bits: u128) -> usize {
+        match self {
+            Reduction::Modulo => (hash as u128 % bits) as usize,
+            Reduction::MultiplyShift => ((hash as u128 * bits) >> 64) as usize,
+        }
+    }
+
+    /// Returns the bit index of the i-th hash of `base` in a filter of `bits`
+    /// bits
+    #[inline]
+    fn position(self, base: &[u128; 2], i: usize, bits: u128) -> usize {
+        if bits > WIDE_HASH_BITS {
+            self.index(BloomFilter::nth_hash(base, i), bits)
+        } else {
+            self.index_64(BloomFilter::nth_hash_64(base, i), bits)
+        }
+    }
+}
+
+/// What `add` does once the filter holds `capacity` items
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Keep adding, fp grows past the configured one
+    #[default]
+    Ignore,
+    /// Skip the item, the filter is left untouched
+    Reject,
+    /// Panic
+    Panic,
+}
+
+/// Key metrics of a filter, serialized by `stats_json`
+#[cfg(feature = "stats-json")]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StatsJson {
+    /// Total size of storage in _bytes_
+    pub size: usize,
+    /// Number of passes for hash functions, of the active sub-filter for
+    /// `DynamicBloom`
+    pub k: usize,
+    /// Number of stored items
+    pub items: u64,
+    /// Current fp rate
+    pub fp: f64,
+    /// Number of sub-filters, `1` for `BloomFilter`
+    pub partitions: usize,
+}
+
+/// Last computed fp together with the stored items count it was computed for
+#[derive(Default)]
+struct FpCache {
+    // stored items count + 1, zero means nothing is cached
+    count: AtomicU64,
+    // f64 bits
+    fp: AtomicU64,
+}
+
+impl FpCache {
+    fn get_or_compute<F: FnOnce() -> f64>(&self, count: u64, compute: F) -> f64 {
+        let key = count.wrapping_add(1);
+        if self.count.load(Ordering::Acquire) == key {
+            return f64::from_bits(self.fp.load(Ordering::Relaxed));
+        }
+        let fp = compute();
+        self.fp.store(fp.to_bits(), Ordering::Relaxed);
+        self.count.store(key, Ordering::Release);
+        fp
+    }
+}
+
+impl Clone for FpCache {
+    fn clone(&self) -> Self {
+        FpCache {
+            count: AtomicU64::new(self.count.load(Ordering::Acquire)),
+            fp: AtomicU64::new(self.fp.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct BloomFilter {
     // storage
     array: BitVec,
@@ -21,6 +374,20 @@ pub struct BloomFilter {
     stored_items: u64,
     // False probability rate
     fp: f64,
+    // fp for current stored_items
+    fp_cache: FpCache,
+    // mixed into the seeds of the two base hashes, so filters with different
+    // seeds are independent
+    seeds: [u64; 2],
+    // whether `add` increments stored_items
+    track_counts: bool,
+    // how hashes are mapped to bit indices
+    reduction: Reduction,
+    // what adding past capacity does
+    on_overflow: OverflowPolicy,
+    // shard id which set the bit, for bits set by `union_tracked`
+    #[cfg(feature = "provenance")]
+    provenance: HashMap<usize, u16>,
 }
 ///
 ///  Terms/Parameters:
@@ -36,36 +403,394 @@ impl BloomFilter {
     }
 
     pub fn with_parameters(size: usize, k: usize, fp: f64) -> Self {
+        BloomFilter::try_with_parameters(size, k, fp).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Same as `with_parameters`, but returns an error instead of panicking.
+    /// The storage is allocated fallibly, so sizes the allocator refuses give
+    /// `AllocFailed` rather than aborting the process. With the
+    /// `self-check` feature, filters failing `self_check` give
+    /// `DegenerateHashing`.
+    ///
+    /// `k` larger than the number of bits is clamped to it, since there are no
+    /// more distinct positions. Such tiny filters still have no false
+    /// negatives, but every item sets most of the bits, so fp quickly
+    /// approaches 1.
+    pub fn try_with_parameters(size: usize, k: usize, fp: f64) -> Result<Self, BloomError> {
+        BloomFilter::validate_fp(fp)?;
+        if size == 0 {
+            return Err(BloomError::ZeroSize);
+        }
+        let nbits = size.checked_mul(8).ok_or(BloomError::AllocFailed)?;
         let capacity = BloomFilter::calculate_capacity_from_fp_size(fp, size);
-        let nbits = size * 8;
-        assert!(
-            capacity > 0,
-            "Given parameters is too small to create a filter"
-        );
-        Self {
-            array: BitVec::from_elem(nbits, false),
+        if capacity == 0 {
+            return Err(BloomError::ZeroCapacity);
+        }
+        let f = Self {
+            array: BloomFilter::try_alloc_bits(nbits)?,
             size,
-            k,
+            k: k.min(nbits),
             capacity,
             fp,
             stored_items: 0,
+            fp_cache: FpCache::default(),
+            seeds: [0, 0],
+            track_counts: true,
+            reduction: Reduction::Modulo,
+            on_overflow: OverflowPolicy::Ignore,
+            #[cfg(feature = "provenance")]
+            provenance: HashMap::new(),
+        };
+        #[cfg(feature = "self-check")]
+        f.self_check().map_err(|_| BloomError::DegenerateHashing)?;
+        Ok(f)
+    }
+
+    /// Allocates `nbits` unset bits without aborting on allocation failure
+    fn try_alloc_bits(nbits: usize) -> Result<BitVec, BloomError> {
+        let blocks = nbits.div_ceil(32);
+        let mut storage: Vec<u32> = Vec::new();
+        storage
+            .try_reserve_exact(blocks)
+            .map_err(|_| BloomError::AllocFailed)?;
+        storage.resize(blocks, 0);
+        let mut array = BitVec::new();
+        // safe: the storage holds exactly the blocks for `nbits` bits, all
+        // of them unset, including the unused tail of the last block
+        unsafe {
+            *array.storage_mut() = storage;
+            array.set_len(nbits);
+        }
+        Ok(array)
+    }
+
+    /// Wraps an existing bit array as a filter with given k and fp, without
+    /// copying it. The number of stored items is estimated from set bits.
+    /// `k` is clamped to the number of bits, as in `try_with_parameters`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let f = bloom::BloomFilter::from_bitvec(bit_vec::BitVec::from_elem(64, false), 3, 0.1);
+    /// assert_eq!(f.bits(), 64);
+    /// ```
+    pub fn from_bitvec(array: BitVec, k: usize, fp: f64) -> Self {
+        assert!(array.len() & 7 == 0, "Bit length should be a multiple of 8");
+        BloomFilter::validate_fp(fp).unwrap_or_else(|e| panic!("{}", e));
+        let size = array.len() / 8;
+        let k = k.min(array.len());
+        let mut f = Self {
+            array,
+            size,
+            k,
+            capacity: BloomFilter::calculate_capacity_from_fp_size(fp, size),
+            fp,
+            stored_items: 0,
+            fp_cache: FpCache::default(),
+            seeds: [0, 0],
+            track_counts: true,
+            reduction: Reduction::Modulo,
+            on_overflow: OverflowPolicy::Ignore,
+            #[cfg(feature = "provenance")]
+            provenance: HashMap::new(),
+        };
+        f.stored_items = f.estimate_items(f.set_bits()).round() as u64;
+        f
+    }
+
+    fn validate_fp(fp: f64) -> Result<(), BloomError> {
+        if fp > 0.0 && fp < 1.0 {
+            Ok(())
+        } else {
+            Err(BloomError::InvalidFp)
         }
     }
 
-    /// Returns current fp rate
+    /// Returns the configured fp for an empty filter and `current_fp` otherwise
+    #[deprecated(note = "use `target_fp` or `current_fp`")]
     pub fn fp(&self) -> f64 {
-        if self.stored_items == 0 {
-            self.fp
+        if self.track_counts && self.stored_items == 0 {
+            self.target_fp()
+        } else {
+            self.current_fp()
+        }
+    }
+
+    /// Returns the fp the filter was configured with
+    pub fn target_fp(&self) -> f64 {
+        self.fp
+    }
+
+    /// Returns fp for currently stored items, `0` for an empty filter. Without
+    /// counting (see `with_track_counts`) it's estimated from occupancy
+    /// instead, fill_ratio ^ k.
+    pub fn current_fp(&self) -> f64 {
+        if !self.track_counts {
+            self.fill_ratio().powi(self.k as i32)
+        } else if self.stored_items == 0 {
+            0.0
         } else {
-            BloomFilter::calculate_fp_from_capacity_size(self.size, self.stored_items)
+            self.fp_cache.get_or_compute(self.stored_items, || {
+                BloomFilter::calculate_fp_from_capacity_size(self.size, self.stored_items)
+            })
         }
     }
 
     /// Creates a bloomfilter with defined false probability and expected number of elements
+    /// (see `params::plan`)
     pub fn with_fp_size(fp: f64, expected: u64) -> Self {
+        let plan = params::plan(fp, expected);
+        BloomFilter::new(plan.size_bytes, plan.k, fp)
+    }
+
+    /// Creates a bloomfilter from total bits count `m_bits` (rounded up to whole
+    /// bytes) and expected number of elements `n`, with optimal k and the
+    /// resulting false probability
+    pub fn with_bits_and_items(m_bits: usize, n: u64) -> Self {
+        let size = (m_bits as f64 / 8.0).ceil() as usize;
+        // optimal k rounds to 0 when m is small relative to n
+        let k = BloomFilter::calculate_k(size, n).max(1);
+        let fp = BloomFilter::calculate_fp_from_capacity_size(size, n);
+        BloomFilter::new(size, k as usize, fp)
+    }
+
+    /// Creates a bloomfilter meeting `fp` for `expected` items with k chosen
+    /// for a workload doing `reads_per_write` lookups per insert.
+    ///
+    /// Every k has its own minimal size holding `fp`,
+    /// m(k) = -k * n / ln(1 - fp^(1/k)), smallest at the fp-optimal k_opt.
+    /// Inserts always hash k times while lookups are mostly for absent items
+    /// and stop at the first unset bit, so only the write share of operations
+    /// pays for k. The chosen k minimizes
+    /// cost(k) = m(k) / m(k_opt) + (k / k_opt) / (1 + reads_per_write),
+    /// so write-heavy workloads trade some memory for fewer hashes.
+    pub fn for_workload(fp: f64, expected: u64, reads_per_write: f64) -> Self {
+        assert!(
+            fp > 0.0 && fp < 1.0,
+            "False probability should be within (0, 1)"
+        );
+        assert!(expected != 0);
+        assert!(
+            reads_per_write >= 0.0,
+            "Reads per write should be non-negative"
+        );
+        let bits_for = |k: f64| BloomFilter::calculate_bits_for_k(fp, expected, k);
+        let k_opt = (-fp.log2()).round().max(1.0);
+        let m_opt = bits_for(k_opt);
+        let write_share = 1.0 / (1.0 + reads_per_write);
+        let cost = |k: f64| bits_for(k) / m_opt + (k / k_opt) * write_share;
+        let k = (1..=k_opt as usize)
+            .map(|k| k as f64)
+            .min_by(|a, b| cost(*a).partial_cmp(&cost(*b)).expect("Cost is finite"))
+            .expect("k_opt is at least 1");
+        let size = (bits_for(k) / 8.0).ceil() as usize;
+        let mut f = BloomFilter::new(size, k as usize, fp);
+        f.capacity = BloomFilter::calculate_capacity_for_k(fp, size, k as usize);
+        f
+    }
+
+    /// Creates a bloomfilter with defined false probability and expected number
+    /// of elements using at most `max_k` hashes. If the optimal k is larger,
+    /// the filter gets more bits instead, so `fp` still holds with `max_k`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let f = bloom::BloomFilter::with_fp_max_k(0.0001, 1000, 4);
+    /// assert!(f.positions(&42).len() <= 4);
+    /// ```
+    pub fn with_fp_max_k(fp: f64, expected: u64, max_k: usize) -> Self {
+        assert!(max_k != 0, "Max k should be non-zero");
+        let f = BloomFilter::with_fp_size(fp, expected);
+        if f.k <= max_k {
+            return f;
+        }
+        let bits = BloomFilter::calculate_bits_for_k(fp, expected, max_k as f64);
+        let size = (bits / 8.0).ceil() as usize;
+        let mut f = BloomFilter::new(size, max_k, fp);
+        f.capacity = BloomFilter::calculate_capacity_for_k(fp, size, max_k);
+        f
+    }
+
+    /// Creates a bloomfilter with defined false probability sized for the given
+    /// set and adds all of its items
+    pub fn from_set<I: Hash>(items: &HashSet<I>, fp: f64) -> Self {
+        let mut f = BloomFilter::with_fp_size(fp, items.len() as u64);
+        for item in items {
+            f.add(item);
+        }
+        f
+    }
+
+    /// Rebuilds a filter from source `items`, keeping only the ones `keep`
+    /// accepts, which is the only way to drop items from a Bloom filter. The
+    /// filter is sized for the kept items.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let f = bloom::BloomFilter::rebuild_retaining(&[1, 2, 3], |i| *i != 2, 0.01);
+    /// assert!(f.get(&1) && f.get(&3));
+    /// assert_eq!(f.stored(), 2);
+    /// ```
+    pub fn rebuild_retaining<I: Hash, F: Fn(&I) -> bool>(items: &[I], keep: F, fp: f64) -> Self {
+        let kept = items.iter().filter(|item| keep(item)).count() as u64;
+        let mut f = BloomFilter::with_fp_size(fp, kept.max(1));
+        f.add_all(items.iter().filter(|item| keep(item)));
+        f
+    }
+
+    /// Creates a bloomfilter with defined false probability sized for
+    /// `max_items` and adds all items from `iter`. Fails if `iter` yields more
+    /// than `max_items` items.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let f = bloom::BloomFilter::build(0.01, 10, 0..10).unwrap();
+    /// assert!(f.get(&5));
+    /// assert!(bloom::BloomFilter::build(0.01, 10, 0..11).is_err());
+    /// ```
+    pub fn build<I: Hash>(
+        fp: f64,
+        max_items: u64,
+        iter: impl Iterator<Item = I>,
+    ) -> Result<Self, CapacityExceeded> {
+        let mut f = BloomFilter::with_fp_size(fp, max_items);
+        for item in iter {
+            if f.stored_items >= max_items {
+                return Err(CapacityExceeded);
+            }
+            f.add(item);
+        }
+        Ok(f)
+    }
+
+    /// Same as `with_fp_size`, but returns an error instead of panicking
+    pub fn try_with_fp_size(fp: f64, expected: u64) -> Result<Self, BloomError> {
+        BloomFilter::validate_fp(fp)?;
+        if expected == 0 {
+            return Err(BloomError::ZeroCapacity);
+        }
         let size = BloomFilter::calculate_size_from_fp_capacity(fp, expected);
         let k = BloomFilter::calculate_k(size, expected);
-        BloomFilter::new(size, k as usize, fp)
+        BloomFilter::try_with_parameters(size, k as usize, fp)
+    }
+
+    /// Returns the best false probability achievable when `expected` items are
+    /// stored in a filter of given size in _bytes_
+    pub fn best_fp_for(bytes: usize, expected: u64) -> f64 {
+        BloomFilter::calculate_fp_from_capacity_size(bytes, expected)
+    }
+
+    /// Sets the salt mixed into every hash, so filters with different salts
+    /// map the same items to unrelated bits (e.g. for independent A/B filters
+    /// over the same keys). Only filters with the same salt can be combined.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut f = bloom::BloomFilter::with_fp_size(0.01, 100).with_salt(7);
+    /// f.add(&42);
+    /// assert!(f.get(&42));
+    /// ```
+    pub fn with_salt(mut self, salt: u64) -> Self {
+        assert!(
+            self.stored_items == 0,
+            "Salt can only be changed on an empty filter"
+        );
+        self.seeds = [salt, salt];
+        self
+    }
+
+    /// Creates new bloomfilter from given size and k, seeding the first and
+    /// the second base hash with `seeds[0]` and `seeds[1]`. Filters with the
+    /// same parameters and seeds set the same bits on every node, `[s, s]` is
+    /// the same as `with_salt(s)`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bloom::BloomFilter;
+    /// let mut a = BloomFilter::with_seeds(64, 3, 0.1, [1, 2]);
+    /// let mut b = BloomFilter::with_seeds(64, 3, 0.1, [1, 2]);
+    /// a.add(&42);
+    /// b.add(&42);
+    /// assert!(a.is_compatible_with(&b));
+    /// assert_eq!(a.fingerprint(), b.fingerprint());
+    /// ```
+    pub fn with_seeds(size: usize, k: usize, fp: f64, seeds: [u64; 2]) -> Self {
+        let mut f = BloomFilter::new(size, k, fp);
+        f.seeds = seeds;
+        f
+    }
+
+    /// Returns seeds of the two base hashes
+    pub fn seeds(&self) -> [u64; 2] {
+        self.seeds
+    }
+
+    /// Sets how hashes are reduced to bit indices. `Reduction::Modulo` is the
+    /// default and keeps existing bit layouts, `Reduction::MultiplyShift` is
+    /// unbiased. Only filters with the same reduction can be combined.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bloom::{BloomFilter, Reduction};
+    /// let mut f = BloomFilter::with_fp_size(0.01, 100).with_reduction(Reduction::MultiplyShift);
+    /// f.add(&42);
+    /// assert!(f.get(&42));
+    /// ```
+    pub fn with_reduction(mut self, reduction: Reduction) -> Self {
+        assert!(
+            self.stored_items == 0,
+            "Reduction can only be changed on an empty filter"
+        );
+        self.reduction = reduction;
+        self
+    }
+
+    /// Returns how hashes are reduced to bit indices
+    pub fn reduction(&self) -> Reduction {
+        self.reduction
+    }
+
+    /// Sets what adding into a filter which already holds `capacity` items
+    /// does, `OverflowPolicy::Ignore` by default. Only counted items are
+    /// checked, see `set_track_counts`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bloom::{BloomFilter, OverflowPolicy};
+    /// let mut f = BloomFilter::with_fp_size(0.1, 1).with_overflow_policy(OverflowPolicy::Reject);
+    /// f.add(&1);
+    /// f.add(&2);
+    /// assert_eq!(f.stored(), f.capacity());
+    /// ```
+    pub fn with_overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.on_overflow = policy;
+        self
+    }
+
+    /// Enables or disables counting of stored items, see `set_track_counts`
+    pub fn with_track_counts(mut self, track_counts: bool) -> Self {
+        self.set_track_counts(track_counts);
+        self
+    }
+
+    /// Enables or disables counting of stored items, e.g. to skip the counter
+    /// in a write-only load phase.
+    ///
+    /// While disabled, `stored` keeps the count it had when counting was
+    /// disabled, `try_add` never fails and `fp` is estimated from occupancy.
+    /// Re-enabling estimates the count from set bits.
+    pub fn set_track_counts(&mut self, track_counts: bool) {
+        if track_counts && !self.track_counts {
+            self.stored_items = self.estimate_items(self.set_bits()).round() as u64;
+        }
+        self.track_counts = track_counts;
     }
 
     /// Returns number of currently stored items
@@ -82,6 +807,196 @@ impl BloomFilter {
         self.array.len()
     }
 
+    /// Returns current occupancy stats
+    pub fn stats(&self) -> BloomStats {
+        BloomStats {
+            stored_items: self.stored_items,
+            fill_ratio: self.fill_ratio(),
+            fp: self.current_fp(),
+        }
+    }
+
+    /// Returns key metrics as a compact JSON object, e.g. for ops tooling
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let f = bloom::BloomFilter::new(16, 3, 0.1);
+    /// assert_eq!(f.stats_json(), r#"{"size":16,"k":3,"items":0,"fp":0.0,"partitions":1}"#);
+    /// ```
+    #[cfg(feature = "stats-json")]
+    pub fn stats_json(&self) -> String {
+        let stats = StatsJson {
+            size: self.size,
+            k: self.k,
+            items: self.stored_items,
+            fp: self.current_fp(),
+            partitions: 1,
+        };
+        serde_json::to_string(&stats).expect("Stats should be serializable")
+    }
+
+    /// Returns one-line human readable summary of parameters and occupancy,
+    /// e.g. for CLI output
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let f = bloom::BloomFilter::new(16, 3, 0.1);
+    /// assert_eq!(f.describe(), "m=128, k=3, fp_target=0.1, fp_now=0.000e0, n=0, fill=0.0000");
+    /// ```
+    pub fn describe(&self) -> String {
+        format!(
+            "m={}, k={}, fp_target={}, fp_now={:.3e}, n={}, fill={:.4}",
+            self.bits(),
+            self.k,
+            self.target_fp(),
+            self.current_fp(),
+            self.stored_items,
+            self.fill_ratio()
+        )
+    }
+
+    /// Returns 64-bit FNV-1a hash of size, k, fp and the bit array, e.g. as a
+    /// cache key. It doesn't depend on the hashing backend or platform, and any
+    /// single changed bit changes it.
+    pub fn fingerprint(&self) -> u64 {
+        const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+        const FNV_PRIME: u64 = 0x100000001b3;
+        let mut hash = FNV_OFFSET;
+        let mut write = |bytes: &[u8]| {
+            for b in bytes {
+                hash = (hash ^ *b as u64).wrapping_mul(FNV_PRIME);
+            }
+        };
+        write(&(self.size as u64).to_le_bytes());
+        write(&(self.k as u64).to_le_bytes());
+        write(&self.fp.to_bits().to_le_bytes());
+        for block in self.array.blocks() {
+            write(&block.to_le_bytes());
+        }
+        hash
+    }
+
+    /// Overwrites storage with zeroes in a way the compiler can't elide
+    #[cfg(feature = "zeroize")]
+    fn wipe(&mut self) {
+        // only the words are overwritten, the length stays valid
+        unsafe { self.array.storage_mut() }.as_mut_slice().zeroize();
+    }
+
+    /// Returns number of bits set in storage
+    pub fn set_bits(&self) -> usize {
+        self.array.blocks().map(|b| b.count_ones() as usize).sum()
+    }
+
+    /// Returns how many 64-bit words of storage have each popcount, indexed by
+    /// popcount `0..=64`. Well distributed hashes give a roughly binomial shape
+    /// around the fill ratio, skew points to clustering. Trailing bits short
+    /// of a whole word are left out, they'd show up as a sparse word.
+    pub fn word_popcount_histogram(&self) -> Vec<u32> {
+        let mut histogram = vec![0u32; 65];
+        let mut blocks = self.array.blocks();
+        for _ in 0..self.bits() / 64 {
+            let mut ones = || blocks.next().map_or(0, |b| b.count_ones());
+            histogram[(ones() + ones()) as usize] += 1;
+        }
+        histogram
+    }
+
+    /// Returns the number of set bits expected after `stored_items` distinct
+    /// inserts, m * (1 - (1 - 1/m)^(kn)). A `set_bits` far below this points
+    /// to hashing anomalies.
+    pub fn expected_set_bits(&self) -> f64 {
+        let bits = self.bits() as f64;
+        let draws = self.k as f64 * self.stored_items as f64;
+        bits * (1.0 - (1.0 - 1.0 / bits).powf(draws))
+    }
+
+    /// Returns the occupancy based fp, fill_ratio ^ k, divided by the stored
+    /// items based one, `1` for an empty filter. Values far from 1 mean the
+    /// count doesn't match the bits, e.g. after adding many duplicates (below
+    /// 1) or with degenerate hashing.
+    pub fn fp_model_drift(&self) -> f64 {
+        if self.stored_items == 0 {
+            return 1.0;
+        }
+        let model = BloomFilter::calculate_fp_from_capacity_size(self.size, self.stored_items);
+        self.fill_ratio().powi(self.k as i32) / model
+    }
+
+    /// Returns fraction of bits set in storage
+    pub fn fill_ratio(&self) -> f64 {
+        self.set_bits() as f64 / self.bits() as f64
+    }
+
+    /// Estimates `fill_ratio` from `sample_words` 32-bit words of storage
+    /// instead of all of them, e.g. for frequent monitoring of a huge filter.
+    /// Words are drawn with a fixed-seed splitmix64, so repeated calls agree,
+    /// while periodic bit patterns (e.g. after `fold`) can't line up with the
+    /// sample the way they would with a fixed stride. The error shrinks with
+    /// 1/sqrt(sample bits).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut f = bloom::BloomFilter::with_fp_size(0.01, 10_000);
+    /// f.add_all(0..5000);
+    /// assert!((f.approx_fill_ratio(64) - f.fill_ratio()).abs() < 0.1);
+    /// ```
+    pub fn approx_fill_ratio(&self, sample_words: usize) -> f64 {
+        assert!(sample_words != 0, "Sample should be non-empty");
+        let words = self.array.storage();
+        if sample_words >= words.len() {
+            return self.fill_ratio();
+        }
+        let len = words.len() as u64;
+        let last = words.len() - 1;
+        let mut state = 0x853c49e6748fea9b_u64;
+        let mut next_word = move || {
+            state = state.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            ((z ^ (z >> 31)) % len) as usize
+        };
+        let (set, total) =
+            (0..sample_words)
+                .map(|_| next_word())
+                .fold((0, 0), |(set, total), idx| {
+                    // the last word may be partially used
+                    let word_bits = if idx == last {
+                        self.bits() - 32 * last
+                    } else {
+                        32
+                    };
+                    (set + words[idx].count_ones() as usize, total + word_bits)
+                });
+        set as f64 / total as f64
+    }
+
+    /// Returns how many more distinct items can be added before the fill
+    /// ratio is expected to reach `target_ratio`, using the expected set bits
+    /// model m * (1 - e^(-kn/m))
+    pub fn items_until_fill(&self, target_ratio: f64) -> u64 {
+        assert!(
+            target_ratio > 0.0 && target_ratio < 1.0,
+            "Target fill ratio should be in (0, 1)"
+        );
+        let bits = self.bits() as f64;
+        let total = -(bits / self.k as f64) * (1.0 - target_ratio).ln();
+        (total as u64).saturating_sub(self.stored_items)
+    }
+
+    /// Estimates number of stored items from given count of set bits
+    /// (Swamidass & Baldi), n = -(m / k) * ln(1 - X / m)
+    fn estimate_items(&self, set_bits: usize) -> f64 {
+        let bits = self.bits() as f64;
+        // a saturated filter would give an infinite estimate
+        let set = (set_bits as f64).min(bits - 1.0);
+        -(bits / self.k as f64) * (1.0 - set / bits).ln()
+    }
+
     /// Calculates size in _bytes_ from given false probability and expected capacity
     fn calculate_size_from_fp_capacity(fp: f64, expected: u64) -> usize {
         assert!(fp != 0f64);
@@ -112,6 +1027,20 @@ impl BloomFilter {
         capacity as u64 // ceil?
     }
 
+    /// Calculates bits count holding `fp` for `expected` items with given k,
+    /// m = -k * n / ln(1 - fp^(1/k))
+    fn calculate_bits_for_k(fp: f64, expected: u64, k: f64) -> f64 {
+        -k * expected as f64 / (1.0 - fp.powf(1.0 / k)).ln()
+    }
+
+    /// Calculates number of items for which fp will be held true with given k
+    /// from size in _bytes_, n = -(m / k) * ln(1 - fp^(1/k))
+    fn calculate_capacity_for_k(fp: f64, bytes: usize, k: usize) -> u64 {
+        let bits = (bytes * 8) as f64;
+        let k = k as f64;
+        (-(bits / k) * (1.0 - fp.powf(1.0 / k)).ln()) as u64
+    }
+
     // Calculates optimal k value
     fn calculate_k(bytes: usize, capacity: u64) -> u32 {
         let bits = (bytes * 8) as f64;
@@ -122,13 +1051,22 @@ impl BloomFilter {
         k as u32
     }
 
+    /// Folds a 64-bit seed into the 32-bit Murmur3 seed
+    #[cfg(feature = "fasthash")]
     #[inline]
-    fn _mmr3_hash<T: Hash>(t: &T) -> u128 {
-        let mut s = Murmur3HasherExt::with_seed(0);
-        t.hash(&mut s);
-        s.finish_ext()
+    fn _mmr3_seed(seed: u64) -> u32 {
+        (seed ^ (seed >> 32)) as u32
     }
 
+    #[cfg(feature = "fasthash")]
+    #[inline]
+    fn _mmr3_hash<T: Hash>(t: &T, seed: u64) -> u128 {
+        let mut s = Murmur3HasherExt::with_seed(BloomFilter::_mmr3_seed(seed));
+        t.hash(&mut s);
+        s.finish_ext()
+    }
+
+    #[cfg(feature = "fasthash")]
     #[inline]
     fn _spooky_hash<T: Hash>(t: &T) -> u128 {
         let mut s = SpookyHasherExt::with_seed((0, 0));
@@ -136,59 +1074,389 @@ impl BloomFilter {
         s.finish_ext()
     }
 
+    #[cfg(feature = "fasthash")]
     #[inline]
-    fn _xxh3_hash<T: Hash>(t: &T) -> u128 {
-        let mut s = XXH3HasherExt::with_seed(0);
+    fn _xxh3_hash<T: Hash>(t: &T, seed: u64) -> u128 {
+        let mut s = XXH3HasherExt::with_seed(seed);
         t.hash(&mut s);
         s.finish_ext()
     }
 
+    #[cfg(feature = "fasthash")]
+    #[inline]
+    fn _mmr3_hash_bytes(bytes: &[u8], seed: u64) -> u128 {
+        fasthash::murmur3::hash128_with_seed(bytes, BloomFilter::_mmr3_seed(seed))
+    }
+
+    #[cfg(feature = "fasthash")]
+    #[inline]
+    fn _xxh3_hash_bytes(bytes: &[u8], seed: u64) -> u128 {
+        fasthash::xxh3::hash128_with_seed(bytes, seed)
+    }
+
+    #[cfg(feature = "portable-hash")]
+    #[inline]
+    fn _sip_hash<F: Fn(&mut sip::SipHasher13)>(keys: (u64, u64), seed: u64, write: F) -> u128 {
+        let mut s = sip::SipHasher13::new_with_keys(keys.0 ^ seed, keys.1 ^ seed);
+        write(&mut s);
+        s.finish128()
+    }
+
+    #[cfg(all(feature = "ahash", not(feature = "portable-hash")))]
+    #[inline]
+    fn _ahash<F: Fn(&mut ahash::AHasher)>(keys: [u64; 4], seed: u64, write: F) -> u64 {
+        let state = ahash::RandomState::with_seeds(keys[0] ^ seed, keys[1], keys[2], keys[3]);
+        let mut s = state.build_hasher();
+        write(&mut s);
+        s.finish()
+    }
+
+    /// Computes the two 128-bit base hashes every other hash is derived from.
+    /// `seeds[0]` and `seeds[1]` seed the first and the second one, `[0, 0]`
+    /// keeps the unseeded hashes.
+    #[cfg(all(
+        feature = "fasthash",
+        not(any(feature = "ahash", feature = "portable-hash"))
+    ))]
+    #[inline]
+    fn base_hashes<I: Hash>(item: &I, seeds: [u64; 2]) -> [u128; 2] {
+        let hashes = HASH_SCRATCH.try_with(|scratch| {
+            let mut bytes = scratch.try_borrow_mut().ok()?;
+            bytes.clear();
+            item.hash(&mut ByteSink(&mut bytes));
+            let hashes = BloomFilter::base_hashes_bytes(&bytes, seeds);
+            if bytes.capacity() > MAX_SCRATCH_BYTES {
+                *bytes = Vec::new();
+            }
+            Some(hashes)
+        });
+        // the buffer is borrowed when `item.hash` itself hashes into a filter,
+        // and gone when called from another thread local's destructor
+        hashes.ok().flatten().unwrap_or_else(|| {
+            [
+                BloomFilter::_mmr3_hash(item, seeds[0]),
+                BloomFilter::_xxh3_hash(item, seeds[1]),
+            ]
+        })
+    }
+
+    /// Computes the two 128-bit base hashes with SipHash-1-3, which gives the
+    /// same bits on every target
+    #[cfg(feature = "portable-hash")]
+    #[inline]
+    fn base_hashes<I: Hash>(item: &I, seeds: [u64; 2]) -> [u128; 2] {
+        [
+            BloomFilter::_sip_hash(SIP_KEYS[0], seeds[0], |s| item.hash(s)),
+            BloomFilter::_sip_hash(SIP_KEYS[1], seeds[1], |s| item.hash(s)),
+        ]
+    }
+
+    /// Computes the two 128-bit base hashes from four 64-bit ahash passes
+    #[cfg(all(feature = "ahash", not(feature = "portable-hash")))]
+    #[inline]
+    fn base_hashes<I: Hash>(item: &I, seeds: [u64; 2]) -> [u128; 2] {
+        let h =
+            |i: usize| BloomFilter::_ahash(AHASH_SEEDS[i], seeds[i / 2], |s| item.hash(s)) as u128;
+        [h(0) << 64 | h(1), h(2) << 64 | h(3)]
+    }
+
+    /// Same as `base_hashes`, but feeds the bytes straight to the hashers
+    /// instead of going through `Hash`
+    #[cfg(all(
+        feature = "fasthash",
+        not(any(feature = "ahash", feature = "portable-hash"))
+    ))]
+    #[inline]
+    fn base_hashes_bytes(bytes: &[u8], seeds: [u64; 2]) -> [u128; 2] {
+        [
+            BloomFilter::_mmr3_hash_bytes(bytes, seeds[0]),
+            BloomFilter::_xxh3_hash_bytes(bytes, seeds[1]),
+        ]
+    }
+
+    #[cfg(all(feature = "ahash", not(feature = "portable-hash")))]
+    #[inline]
+    fn base_hashes_bytes(bytes: &[u8], seeds: [u64; 2]) -> [u128; 2] {
+        let h = |i: usize| {
+            BloomFilter::_ahash(AHASH_SEEDS[i], seeds[i / 2], |s| s.write(bytes)) as u128
+        };
+        [h(0) << 64 | h(1), h(2) << 64 | h(3)]
+    }
+
+    #[cfg(feature = "portable-hash")]
+    #[inline]
+    fn base_hashes_bytes(bytes: &[u8], seeds: [u64; 2]) -> [u128; 2] {
+        [
+            BloomFilter::_sip_hash(SIP_KEYS[0], seeds[0], |s| s.write(bytes)),
+            BloomFilter::_sip_hash(SIP_KEYS[1], seeds[1], |s| s.write(bytes)),
+        ]
+    }
+
+    /// Expands a single 64-bit hash into two base hashes with splitmix64
+    #[inline]
+    fn base_hashes_from(hash: u64, seeds: [u64; 2]) -> [u128; 2] {
+        let mut state = hash ^ seeds[0];
+        let mut next = |reseed: u64| {
+            state ^= reseed;
+            state = state.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            (z ^ (z >> 31)) as u128
+        };
+        let first = next(0) << 64 | next(0);
+        // equal seeds continue the same stream for the second hash
+        [first, next(seeds[0] ^ seeds[1]) << 64 | next(0)]
+    }
+
     // We use the results of
     // 'Less Hashing, Same Performance: Building a Better Bloom Filter'
     // https://www.eecs.harvard.edu/~michaelm/postscripts/tr-02-05.pdf, to use
     // g_i(x) = h1(u) + i * h2(u) mod m'
     //
-    pub fn compute_hashes<I: Hash>(&self, item: &I) -> Vec<u64> {
-        let mut result: Vec<u64> = Vec::with_capacity(self.k);
+    // For filters past 2^32 bits the recurrence runs in 128-bit space, so
+    // positions stay well distributed even when m doesn't fit into 64 bits
+    // worth of entropy per hash.
+    #[inline]
+    fn nth_hash(base: &[u128; 2], i: usize) -> u128 {
+        base[0].wrapping_add(base[1].wrapping_mul(i as u128))
+    }
+
+    // Smaller filters keep the original 64-bit recurrence over the four 64-bit
+    // halves of the base hashes, and with it their bit layout
+    #[inline]
+    fn nth_hash_64(base: &[u128; 2], i: usize) -> u64 {
+        let h = [
+            base[0] as u64,
+            (base[0] >> 64) as u64,
+            base[1] as u64,
+            (base[1] >> 64) as u64,
+        ];
+        if i < 4 {
+            h[i]
+        } else {
+            h[1].wrapping_add((h[3].wrapping_mul(i as u64)) % HASH_PRIME)
+        }
+    }
+
+    /// Returns the `k` hashes bit indices are reduced from, 64-bit ones for
+    /// filters up to 2^32 bits
+    pub fn compute_hashes<I: Hash>(&self, item: &I) -> Vec<u128> {
+        let base = BloomFilter::base_hashes(item, self.seeds);
+        let wide = self.bits() as u128 > WIDE_HASH_BITS;
+        let hashes: Vec<u128> = (0..self.k)
+            .map(|i| {
+                if wide {
+                    BloomFilter::nth_hash(&base, i)
+                } else {
+                    BloomFilter::nth_hash_64(&base, i) as u128
+                }
+            })
+            .collect();
+        debug_assert_eq!(hashes.len(), self.k);
+        hashes
+    }
 
-        let h1 = BloomFilter::_mmr3_hash(item);
-        result.push(h1 as u64);
-        result.push((h1 >> 64) as u64);
+    /// Returns the `k` bit indices the item maps to, the same ones `add` sets
+    /// and `get` checks
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let f = bloom::BloomFilter::with_fp_size(0.1, 10);
+    /// assert!(f.positions(&42).iter().all(|&idx| idx < f.bits()));
+    /// ```
+    pub fn positions<I: Hash>(&self, item: I) -> Vec<usize> {
+        self.positions_from(&BloomFilter::base_hashes(&item, self.seeds))
+    }
+
+    /// Returns the average number of distinct bit positions per item of
+    /// `sample`. Values well below `k` point to degenerate hashing, e.g. a
+    /// stride that is a multiple of the bit count.
+    pub fn effective_k<I: Hash>(&self, sample: &[I]) -> f64 {
+        assert!(!sample.is_empty(), "Sample should be non-empty");
+        let distinct: usize = sample
+            .iter()
+            .map(|item| {
+                let mut positions = self.positions(item);
+                positions.sort_unstable();
+                positions.dedup();
+                positions.len()
+            })
+            .sum();
+        distinct as f64 / sample.len() as f64
+    }
 
-        let h2 = BloomFilter::_xxh3_hash(item);
-        result.push(h2 as u64);
-        result.push((h2 >> 64) as u64);
+    /// Checks that hashing is not degenerate: probe items should map to about
+    /// as many distinct bits as `k` random draws from the bit array would,
+    /// which fails e.g. when the stride is zero. Small filters repeat bits by
+    /// construction, so the expectation accounts for that (see below).
+    pub fn self_check(&self) -> Result<(), SelfCheckError> {
+        let seeds = self.seeds;
+        self.check_hashing(|item| BloomFilter::base_hashes(&item, seeds))
+    }
 
-        assert!(result.len() >= 4);
-        for i in 4..self.k {
-            result.insert(
-                i,
-                result[1].wrapping_add((result[3].wrapping_mul(i as u64)) % HASH_PRIME),
-            );
+    fn check_hashing<F: Fn(u64) -> [u128; 2]>(&self, hashes: F) -> Result<(), SelfCheckError> {
+        const PROBES: u64 = 32;
+        // Euler's totient, by trial division
+        fn totient(mut n: usize) -> usize {
+            let mut result = n;
+            let mut p = 2;
+            while p * p <= n {
+                if n.is_multiple_of(p) {
+                    while n.is_multiple_of(p) {
+                        n /= p;
+                    }
+                    result -= result / p;
+                }
+                p += 1;
+            }
+            if n > 1 {
+                result -= result / n;
+            }
+            result
+        }
+        let distinct: usize = (0..PROBES)
+            .map(|item| {
+                let mut positions = self.positions_from(&hashes(item));
+                positions.sort_unstable();
+                positions.dedup();
+                positions.len()
+            })
+            .sum();
+        let effective_k = distinct as f64 / PROBES as f64;
+        let (m, k) = (self.bits(), self.k);
+        let (bits, kf) = (m as f64, k as f64);
+        // k random draws: E = m * (1 - (1 - 1/m)^k),
+        // Var = m * (1 - 1/m)^k + m * (m - 1) * (1 - 2/m)^k - m^2 * (1 - 1/m)^2k
+        let miss = (1.0 - 1.0 / bits).powi(k as i32);
+        let random_k = bits * (1.0 - miss);
+        let random_var = bits * miss + bits * (bits - 1.0) * (1.0 - 2.0 / bits).powi(k as i32)
+            - bits * bits * miss * miss;
+        // double hashing with a stride sharing factor g with m cycles through
+        // m / g bits only, and phi(q) of m strides give a cycle of q
+        let short_cycles: Vec<(f64, f64)> = (1..k.min(m + 1))
+            .filter(|q| m.is_multiple_of(*q))
+            .map(|q| (totient(q) as f64 / bits, q as f64))
+            .collect();
+        let cyclic_k = kf - short_cycles.iter().map(|(w, q)| w * (kf - q)).sum::<f64>();
+        let cyclic_var = kf * kf
+            - short_cycles
+                .iter()
+                .map(|(w, q)| w * (kf * kf - q * q))
+                .sum::<f64>()
+            - cyclic_k * cyclic_k;
+        let expected_k = random_k.min(cyclic_k);
+        // the probe average deviates by std / sqrt(PROBES), allow 4 of those
+        // on top of some slack for the hash itself
+        let noise = 4.0 * (random_var.max(cyclic_var).max(0.0) / PROBES as f64).sqrt();
+        if effective_k < expected_k * 0.9 - noise {
+            return Err(SelfCheckError {
+                effective_k,
+                expected_k,
+            });
         }
+        Ok(())
+    }
 
-        result
+    /// Checks that the recorded parameters agree with each other, e.g. after
+    /// `from_bitvec` with an arbitrary k: capacity should follow from size and
+    /// fp, either for an optimal k or for the actual one (see `with_fp_max_k`),
+    /// and the fp at full capacity with the actual k, (1 - e^(-k * n / m))^k,
+    /// shouldn't exceed the target by more than 25%.
+    ///
+    /// Tiny filters, where rounding up to whole bytes adds most of the
+    /// capacity, report `FpMismatch`.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        const FP_TOLERANCE: f64 = 1.25;
+        let computed = BloomFilter::calculate_capacity_from_fp_size(self.fp, self.size);
+        let for_k = BloomFilter::calculate_capacity_for_k(self.fp, self.size, self.k);
+        if computed != self.capacity && for_k != self.capacity {
+            return Err(ValidationError::CapacityMismatch {
+                recorded: self.capacity,
+                computed,
+            });
+        }
+        let bits = self.bits() as f64;
+        let k = self.k as f64;
+        let fp = (1.0 - (-k * self.capacity as f64 / bits).exp()).powf(k);
+        if fp > self.fp * FP_TOLERANCE {
+            return Err(ValidationError::FpMismatch {
+                target: self.fp,
+                computed: fp,
+            });
+        }
+        Ok(())
     }
 
-    /// Adds item to filter
+    fn positions_from(&self, base: &[u128; 2]) -> Vec<usize> {
+        let bits = self.bits() as u128;
+        (0..self.k)
+            .map(|i| self.reduction.position(base, i, bits))
+            .collect()
+    }
+
+    fn set_positions(&mut self, base: &[u128; 2]) {
+        if self.track_counts && self.stored_items >= self.capacity {
+            match self.on_overflow {
+                OverflowPolicy::Ignore => {}
+                OverflowPolicy::Reject => return,
+                OverflowPolicy::Panic => panic!("Filter capacity exceeded"),
+            }
+        }
+        let bits = self.bits() as u128;
+        for i in 0..self.k {
+            let idx = self.reduction.position(base, i, bits);
+            self.array.set(idx, true);
+        }
+        if self.track_counts {
+            self.stored_items += 1
+        }
+    }
+
+    fn check_positions(&self, base: &[u128; 2]) -> bool {
+        // nothing was added, also covers k = 0 where every item would match
+        if self.track_counts && self.stored_items == 0 {
+            return false;
+        }
+        let bits = self.bits() as u128;
+        // stop at the first unset bit, most lookups are for absent items
+        (0..self.k).all(|i| self.array[self.reduction.position(base, i, bits)])
+    }
+
+    /// Pre-allocates the hashing buffer of the current thread for items writing
+    /// up to `max_item_bytes` bytes into a hasher, so the following `add` and
+    /// `get` calls on this thread don't allocate, e.g. for predictable latency.
+    /// The buffer is capped at 64KiB, larger items allocate on every call.
+    /// Only the default fasthash backend buffers item bytes, for ahash and
+    /// portable-hash this does nothing.
     ///
     /// # Example
     ///
     /// ```
     /// let mut f = bloom::BloomFilter::with_fp_size(0.1, 10);
+    /// f.warm_up(64);
     /// f.add(&42);
-    /// assert!(f.get(&42));
     /// ```
-    pub fn add<I: Hash>(&mut self, item: I) {
-        let hashes = self.compute_hashes(&item);
-        for idx in 0..self.k {
-            let idx = hashes[idx] % self.bits() as u64;
-            self.array.set(idx as usize, true);
-        }
-        self.stored_items += 1
+    pub fn warm_up(&self, max_item_bytes: usize) {
+        #[cfg(all(
+            feature = "fasthash",
+            not(any(feature = "ahash", feature = "portable-hash"))
+        ))]
+        let _ = HASH_SCRATCH.try_with(|scratch| {
+            if let Ok(mut bytes) = scratch.try_borrow_mut() {
+                let additional = max_item_bytes
+                    .min(MAX_SCRATCH_BYTES)
+                    .saturating_sub(bytes.len());
+                bytes.reserve(additional);
+            }
+        });
+        #[cfg(any(feature = "ahash", feature = "portable-hash"))]
+        let _ = max_item_bytes;
     }
 
-    /// Checks that item is in filter
+    /// Adds item to filter. Past capacity it's up to the overflow policy, see
+    /// `with_overflow_policy`.
     ///
     /// # Example
     ///
@@ -196,170 +1464,1919 @@ impl BloomFilter {
     /// let mut f = bloom::BloomFilter::with_fp_size(0.1, 10);
     /// f.add(&42);
     /// assert!(f.get(&42));
-    /// assert!(!f.get(&0));
     /// ```
-    pub fn get<I: Hash>(&self, item: I) -> bool {
-        let mut result = true;
-        let hashes = self.compute_hashes(&item);
-        for idx in 0..self.k {
-            let idx = hashes[idx] % self.bits() as u64;
-            if !self.array[idx as usize] {
-                result = false;
-            }
-        }
-        result
+    pub fn add<I: Hash>(&mut self, item: I) {
+        self.set_positions(&BloomFilter::base_hashes(&item, self.seeds))
     }
 
-    /// Calculates the union of two filters. This acts like the bitwise `or`
-    /// function.
+    /// Adds every item yielded by `items`, without collecting them first
     ///
-    /// Sets `self` to the union of `self` and `other`. Both filters must have
-    /// the same length and k number. Returns `true` if `self` changed.
+    /// # Example
     ///
-    pub fn union(&mut self, other: &Self) -> bool {
-        assert!(
-            self.capacity == other.capacity,
-            "Only unions with the same capacity can be unioned"
-        );
-        assert!(
-            self.k == other.k,
-            "Only unions with the same number of hash functions can be unioned"
-        );
-        self.array.union(&other.array)
+    /// ```
+    /// let mut f = bloom::BloomFilter::with_fp_size(0.1, 10);
+    /// f.add_all("a b c".split(' '));
+    /// assert!(f.get("b"));
+    /// ```
+    pub fn add_all<I: Hash, T: IntoIterator<Item = I>>(&mut self, items: T) {
+        for item in items {
+            self.add(item);
+        }
     }
 
-    /// Calculates the intersection of two filters. This acts like the bitwise `and`
-    /// function.
+    /// Adds every integer key of `range`, same as `add_u64` for each of them.
     ///
-    /// Sets `self` to the intersection of `self` and `other`. Both filters must have
-    /// the same length and k number. Returns `true` if `self` changed.
+    /// Keys are still hashed one by one, so this is O(range length), it only
+    /// saves the iterator boilerplate.
     ///
-    pub fn intersect(&mut self, other: &Self) -> bool {
-        assert!(
-            self.capacity == other.capacity,
-            "Only unions with the same capacity can be unioned"
-        );
-        assert!(
-            self.k == other.k,
-            "Only unions with the same number of hash functions can be unioned"
-        );
-        self.array.intersect(&other.array)
+    /// # Example
+    ///
+    /// ```
+    /// let mut f = bloom::BloomFilter::with_fp_size(0.01, 100);
+    /// f.add_range(10..20);
+    /// assert!(f.get_u64(15));
+    /// ```
+    pub fn add_range(&mut self, range: Range<u64>) {
+        for v in range {
+            self.add_u64(v);
+        }
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use rand::Rng;
 
-    #[derive(Hash, Eq, PartialEq)]
-    struct TestItem {
-        a: u32,
+    /// Adds raw bytes to filter, hashing them directly without `Hash`.
+    ///
+    /// This skips the length prefix `Hash` writes for slices, so bytes added
+    /// here are only visible to `get_bytes`, not to `get(bytes)`, and vice versa.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut f = bloom::BloomFilter::with_fp_size(0.1, 10);
+    /// f.add_bytes(b"key");
+    /// assert!(f.get_bytes(b"key"));
+    /// ```
+    pub fn add_bytes(&mut self, bytes: &[u8]) {
+        self.set_positions(&BloomFilter::base_hashes_bytes(bytes, self.seeds))
     }
 
-    #[test]
-    fn item_in_filter() {
-        let item = TestItem { a: 42 };
-        let mut f = BloomFilter::new(1, 1, 0.1);
-        f.add(&item);
-        assert!(f.get(&item));
+    /// Adds a `u64` key, feeding its 8 bytes straight to the hashers.
+    ///
+    /// `u64` hashes to its bytes without a length prefix, so this sets the same
+    /// bits as `add(&v)` and both `get_u64` and `get(&v)` see the key.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut f = bloom::BloomFilter::with_fp_size(0.1, 10);
+    /// f.add_u64(42);
+    /// assert!(f.get_u64(42) && f.get(&42u64));
+    /// ```
+    pub fn add_u64(&mut self, v: u64) {
+        self.set_positions(&BloomFilter::base_hashes_u64(v, self.seeds))
     }
 
-    #[test]
-    fn false_negatives() {
-        let items = (0..64)
-            .map(|i| TestItem { a: i })
-            .collect::<Vec<TestItem>>();
-        let mut f = BloomFilter::new(8, 2, 0.1);
-        let _: () = items.iter().map(|i| f.add(i)).collect();
-        let mut rng = rand::thread_rng();
-        for _ in 0..128 {
-            let idx = rng.gen_range(0, items.len());
-            let item = &items[idx];
-            assert!(f.get(&item));
+    /// Computes base hashes of `v` the way `Hash` feeds it to the hashers
+    #[inline]
+    fn base_hashes_u64(v: u64, seeds: [u64; 2]) -> [u128; 2] {
+        if cfg!(feature = "portable-hash") {
+            BloomFilter::base_hashes_bytes(&v.to_le_bytes(), seeds)
+        } else if cfg!(feature = "ahash") {
+            // ahash mixes integers in `write_u64` rather than as bytes
+            BloomFilter::base_hashes(&v, seeds)
+        } else {
+            BloomFilter::base_hashes_bytes(&v.to_ne_bytes(), seeds)
         }
     }
 
-    #[test]
-    fn false_positive() {
-        let items = (0..64)
-            .map(|i| TestItem { a: i })
-            .collect::<Vec<TestItem>>();
-        //let mut f = BloomFilter::new(427, 8);
-        let mut f = BloomFilter::with_fp_size(0.01, 64);
-
-        let _: () = items.iter().map(|i| f.add(i)).collect();
-        let mut rng = rand::thread_rng();
-        let false_items = (64..128)
-            .map(|i| TestItem { a: i })
-            .collect::<Vec<TestItem>>();
-
-        let mut positives = 0;
-        for _ in 0..128 {
-            let idx = rng.gen_range(0, false_items.len());
-            let item = &false_items[idx];
-            if f.get(&item) {
-                positives += 1;
-                assert!(!items.contains(&item));
-            }
-        }
-        dbg!(f.array.len());
-        dbg!(positives);
-        dbg!(f.k);
-        assert!(positives <= (false_items.len() / 10));
+    /// Adds a `u128` key (e.g. a UUID), feeding its 16 bytes straight to the
+    /// hashers. Same as `add_u64`, this sets the same bits as `add(&v)`, so
+    /// both `get_u128` and `get(&v)` see the key. A UUID type hashing to its
+    /// bytes with a length prefix maps to different bits than its `u128`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut f = bloom::BloomFilter::with_fp_size(0.1, 10);
+    /// f.add_u128(0x6ba7b810_9dad_11d1_80b4_00c04fd430c8);
+    /// assert!(f.get_u128(0x6ba7b810_9dad_11d1_80b4_00c04fd430c8));
+    /// ```
+    pub fn add_u128(&mut self, v: u128) {
+        self.set_positions(&BloomFilter::base_hashes_u128(v, self.seeds))
     }
 
-    #[test]
-    fn fp_changes_with_inserts() {
-        // we ask for a filter that should be able to hold 16 items with 0.1 false negative rate
-        // and filter will calc optimal k value
-        let mut f = BloomFilter::with_fp_size(0.1, 16);
-        println!("Current capacity/fp: {}/{:1.6}", f.capacity, f.fp());
-        // now insert 32 items which should drive fp up
-        for i in 0..32 {
-            f.add(&TestItem { a: i })
+    /// Computes base hashes of `v` the way `Hash` feeds it to the hashers
+    #[inline]
+    fn base_hashes_u128(v: u128, seeds: [u64; 2]) -> [u128; 2] {
+        if cfg!(feature = "portable-hash") {
+            BloomFilter::base_hashes_bytes(&v.to_le_bytes(), seeds)
+        } else if cfg!(feature = "ahash") {
+            BloomFilter::base_hashes(&v, seeds)
+        } else {
+            BloomFilter::base_hashes_bytes(&v.to_ne_bytes(), seeds)
         }
-        // it should become ~0.3
-        assert!(f.fp() >= 0.3f64);
     }
 
-    #[test]
-    fn stored_items_changed() {
-        let mut f = BloomFilter::with_fp_size(0.1, 16);
-        f.add(&TestItem { a: 42 });
-        assert!(f.stored_items == 1);
+    /// Adds item to filter and returns whether it was (probably) there before,
+    /// hashing the item only once
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut f = bloom::BloomFilter::with_fp_size(0.1, 10);
+    /// assert!(!f.get_and_add(&42));
+    /// assert!(f.get_and_add(&42));
+    /// ```
+    pub fn get_and_add<I: Hash>(&mut self, item: I) -> bool {
+        let base = BloomFilter::base_hashes(&item, self.seeds);
+        let present = self.check_positions(&base);
+        self.set_positions(&base);
+        present
     }
 
-    #[test]
-    fn calc_k() {
-        let k = BloomFilter::calculate_k(512, 5000);
-        assert_eq!(k, 1);
+    /// Adds item to filter using the 64-bit hash returned by `key_fn` instead of
+    /// its `Hash` impl, so only the parts of the item `key_fn` looks at matter.
+    /// Items added this way are only visible to `get_with` with the same `key_fn`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut f = bloom::BloomFilter::with_fp_size(0.1, 10);
+    /// f.add_with(&(1, "a"), |item| item.0);
+    /// assert!(f.get_with(&(1, "b"), |item| item.0));
+    /// ```
+    pub fn add_with<I, F: Fn(&I) -> u64>(&mut self, item: &I, key_fn: F) {
+        self.add_hash(key_fn(item))
     }
 
-    #[test]
-    fn calculate_capacity_from_fp_size() {
-        let capacity = BloomFilter::calculate_capacity_from_fp_size(0.01, 512);
-        assert_eq!(capacity, 427);
+    /// Adds a precomputed 64-bit hash, the `k` positions are derived from it
+    /// the same way as for `add_with`
+    pub fn add_hash(&mut self, hash: u64) {
+        self.set_positions(&BloomFilter::base_hashes_from(hash, self.seeds))
     }
 
-    #[test]
-    fn calculate_fp_from_capacity_size() {
-        let fp = BloomFilter::calculate_fp_from_capacity_size(512, 5000) * 1_000_000.0;
-        assert_eq!(fp.round(), 674633.0);
+    /// Adds item to filter hashing it with `hasher` instead of the filter's
+    /// own hashers, e.g. one keyed per tenant. Items added this way are only
+    /// visible to `get_hashed_by` with an identically keyed hasher.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::collections::hash_map::RandomState;
+    /// use std::hash::BuildHasher;
+    /// let tenant = RandomState::new();
+    /// let mut f = bloom::BloomFilter::with_fp_size(0.01, 10);
+    /// f.add_hashed_by(&42, tenant.build_hasher());
+    /// assert!(f.get_hashed_by(&42, tenant.build_hasher()));
+    /// ```
+    pub fn add_hashed_by<H: Hasher, I: Hash>(&mut self, item: I, mut hasher: H) {
+        item.hash(&mut hasher);
+        self.add_hash(hasher.finish())
     }
 
-    #[test]
-    fn calculate_size_from_fp_capacity() {
-        let size = BloomFilter::calculate_size_from_fp_capacity(0.001, 5000);
-        assert_eq!(size, 8986);
+    /// Adds item to filter unless it already holds `capacity` items, in which
+    /// case the filter is left untouched so the fp guarantee still holds
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut f = bloom::BloomFilter::with_fp_size(0.1, 10);
+    /// assert!(f.try_add(&42).is_ok());
+    /// ```
+    pub fn try_add<I: Hash>(&mut self, item: I) -> Result<(), CapacityExceeded> {
+        if self.track_counts && self.stored_items >= self.capacity {
+            return Err(CapacityExceeded);
+        }
+        self.add(item);
+        Ok(())
     }
 
-    #[test]
-    fn union() {
-        let mut a = BloomFilter::with_fp_size(0.1, 16);
-        a.add(&TestItem { a: 42 });
-        let mut b = BloomFilter::with_fp_size(0.1, 16);
-        b.add(&TestItem { a: 77 });
+    /// Checks that item is in filter
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut f = bloom::BloomFilter::with_fp_size(0.1, 10);
+    /// f.add(&42);
+    /// assert!(f.get(&42));
+    /// assert!(!f.get(&0));
+    /// ```
+    pub fn get<I: Hash>(&self, item: I) -> bool {
+        self.check_positions(&BloomFilter::base_hashes(&item, self.seeds))
+    }
+
+    /// Returns how many of the `k` bits of item are set, probing all of them
+    /// unlike `get`, which stops at the first unset bit. The item is in filter
+    /// if all `k` are set.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut f = bloom::BloomFilter::with_fp_size(0.01, 10);
+    /// f.add(&1);
+    /// assert_eq!(f.matching_bits(&1), f.positions(&1).len());
+    /// ```
+    pub fn matching_bits<I: Hash>(&self, item: I) -> usize {
+        let base = BloomFilter::base_hashes(&item, self.seeds);
+        let bits = self.bits() as u128;
+        (0..self.k)
+            .filter(|&i| self.array[self.reduction.position(&base, i, bits)])
+            .count()
+    }
+
+    /// Checks that item is in filter, returning `None` for a definite miss and
+    /// the probability that a hit is genuine, `1 - current_fp`, otherwise.
+    ///
+    /// The confidence describes the filter as a whole, i.e. what fraction of
+    /// hits for absent items to expect, not this particular item.
+    pub fn get_with_confidence<I: Hash>(&self, item: I) -> Option<f64> {
+        if self.get(item) {
+            Some(1.0 - self.current_fp())
+        } else {
+            None
+        }
+    }
+
+    /// Checks every item of `items` in one call, returning a bitmask with
+    /// bit `i` set if `items[i]` is in filter.
+    ///
+    /// Items are hashed in blocks of 64 into a buffer on the stack, then the
+    /// block is probed, so hashing and bit lookups each run back to back.
+    /// Only the result is allocated, apart from the hashing buffer of the
+    /// fasthash backend (see `warm_up`).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut f = bloom::BloomFilter::with_fp_size(0.01, 10);
+    /// f.add(&1);
+    /// let found = f.get_many(&[1, 2]);
+    /// assert!(found[0] && !found[1]);
+    /// ```
+    pub fn get_many<I: Hash>(&self, items: &[I]) -> BitVec {
+        const BLOCK: usize = 64;
+        let mut found = BitVec::from_elem(items.len(), false);
+        let mut scratch = [[0u128; 2]; BLOCK];
+        for (block_idx, block) in items.chunks(BLOCK).enumerate() {
+            for (base, item) in scratch.iter_mut().zip(block) {
+                *base = BloomFilter::base_hashes(item, self.seeds);
+            }
+            for (i, base) in scratch[..block.len()].iter().enumerate() {
+                if self.check_positions(base) {
+                    found.set(block_idx * BLOCK + i, true);
+                }
+            }
+        }
+        found
+    }
+
+    /// Returns the fraction of `items` present in filter, e.g. to threshold
+    /// similarity of MinHash signatures by their sub-hashes. False positives
+    /// can only raise it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut f = bloom::BloomFilter::with_fp_size(0.001, 10);
+    /// f.add_all(&[1, 2, 3]);
+    /// assert_eq!(f.get_fraction(&[1, 2, 4, 5]), 0.5);
+    /// ```
+    pub fn get_fraction<I: Hash>(&self, items: &[I]) -> f64 {
+        assert!(!items.is_empty(), "Items should be non-empty");
+        let found = items.iter().filter(|item| self.get(item)).count();
+        found as f64 / items.len() as f64
+    }
+
+    /// Checks whether any key derived by `key_fn` from a value in `range` is in
+    /// filter, e.g. `(metric, bucket)` tuples for a range of buckets.
+    ///
+    /// This is not a native Bloom filter capability: every value is probed
+    /// separately, so it's O(range length).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut f = bloom::BloomFilter::with_fp_size(0.01, 10);
+    /// f.add(&("cpu", 5u64));
+    /// assert!(f.any_in_range(0..10, |bucket| ("cpu", bucket)));
+    /// ```
+    pub fn any_in_range<K: Hash, F: Fn(u64) -> K>(&self, range: Range<u64>, key_fn: F) -> bool {
+        range.into_iter().any(|v| self.get(key_fn(v)))
+    }
+
+    /// Checks that item added with `add_with` is in filter
+    pub fn get_with<I, F: Fn(&I) -> u64>(&self, item: &I, key_fn: F) -> bool {
+        self.check_positions(&BloomFilter::base_hashes_from(key_fn(item), self.seeds))
+    }
+
+    /// Checks that item added with `add_hashed_by` is in filter, `hasher`
+    /// should be keyed the same way
+    pub fn get_hashed_by<H: Hasher, I: Hash>(&self, item: I, mut hasher: H) -> bool {
+        item.hash(&mut hasher);
+        self.check_positions(&BloomFilter::base_hashes_from(hasher.finish(), self.seeds))
+    }
+
+    /// Checks a batch of precomputed 64-bit hashes added with `add_hash` or
+    /// `add_with`, returning membership in the same order
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut f = bloom::BloomFilter::with_fp_size(0.01, 10);
+    /// f.add_hash(0xdead_beef);
+    /// assert_eq!(f.contains_hashes(&[0xdead_beef, 42]), vec![true, false]);
+    /// ```
+    pub fn contains_hashes(&self, hashes: &[u64]) -> Vec<bool> {
+        hashes
+            .iter()
+            .map(|hash| self.check_positions(&BloomFilter::base_hashes_from(*hash, self.seeds)))
+            .collect()
+    }
+
+    /// Checks that a `u64` key added with `add_u64` or `add` is in filter
+    pub fn get_u64(&self, v: u64) -> bool {
+        self.check_positions(&BloomFilter::base_hashes_u64(v, self.seeds))
+    }
+
+    /// Checks that a `u128` key added with `add_u128` or `add` is in filter
+    pub fn get_u128(&self, v: u128) -> bool {
+        self.check_positions(&BloomFilter::base_hashes_u128(v, self.seeds))
+    }
+
+    /// Checks whether any integer key of `range` is in filter, probing them one
+    /// by one, O(range length)
+    pub fn get_range_any(&self, range: Range<u64>) -> bool {
+        range.into_iter().any(|v| self.get_u64(v))
+    }
+
+    /// Checks that raw bytes added with `add_bytes` are in filter
+    pub fn get_bytes(&self, bytes: &[u8]) -> bool {
+        self.check_positions(&BloomFilter::base_hashes_bytes(bytes, self.seeds))
+    }
+
+    /// Measures the actual false positive rate by probing `num_probes` random
+    /// `u128` items, which are practically never the inserted ones. Used to
+    /// check the analytic `fp` against hashing quality.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn measure_fp<R: rand::Rng>(&self, num_probes: usize, rng: &mut R) -> f64 {
+        assert!(num_probes != 0, "Number of probes should be non-zero");
+        let positives = (0..num_probes)
+            .filter(|_| self.get(rng.gen::<u128>()))
+            .count();
+        positives as f64 / num_probes as f64
+    }
+
+    /// Computes Pearson's chi-square statistic of how often every bit position
+    /// is hit by the `k` positions of `sample` items, against a uniform spread.
+    /// Uniform hashing gives about `m - 1` (the degrees of freedom) with a
+    /// standard deviation of about `sqrt(2 * (m - 1))`, much larger values
+    /// point to positions clustering, e.g. from degenerate hashing.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn chi_square_uniformity<I: Hash>(&self, sample: &[I]) -> f64 {
+        assert!(!sample.is_empty(), "Sample should be non-empty");
+        let mut hits = vec![0u64; self.bits()];
+        for item in sample {
+            for idx in self.positions(item) {
+                hits[idx] += 1;
+            }
+        }
+        let expected = (sample.len() * self.k) as f64 / self.bits() as f64;
+        hits.iter()
+            .map(|&observed| (observed as f64 - expected).powi(2) / expected)
+            .sum()
+    }
+
+    /// Calculates the union of two filters. This acts like the bitwise `or`
+    /// function.
+    ///
+    /// Sets `self` to the union of `self` and `other`. Both filters must have
+    /// the same length and k number. Returns `true` if `self` changed.
+    /// Stored counts are summed, so items present in both are counted twice.
+    ///
+    pub fn union(&mut self, other: &Self) -> bool {
+        assert!(
+            self.is_compatible_with(other),
+            "Only filters with the same size, k and fp can be unioned"
+        );
+        self.stored_items += other.stored_items;
+        self.array.union(&other.array)
+    }
+
+    /// Same as `union`, but also records `shard_id` for every bit that was
+    /// unset in `self` and is set by `other`, see `bit_provenance`. The table
+    /// takes memory per set bit, so it's only available with the `provenance`
+    /// feature.
+    #[cfg(feature = "provenance")]
+    pub fn union_tracked(&mut self, other: &Self, shard_id: u16) -> bool {
+        assert!(
+            self.is_compatible_with(other),
+            "Only filters with the same size, k and fp can be unioned"
+        );
+        let new_bits: Vec<u32> = self
+            .array
+            .blocks()
+            .zip(other.array.blocks())
+            .map(|(a, b)| !a & b)
+            .collect();
+        for (block_idx, block) in new_bits.into_iter().enumerate() {
+            for bit in 0..32 {
+                if block & (1 << bit) != 0 {
+                    self.provenance.insert(block_idx * 32 + bit, shard_id);
+                }
+            }
+        }
+        self.union(other)
+    }
+
+    /// Returns id of the shard which set bit `idx` in `union_tracked`, if any
+    #[cfg(feature = "provenance")]
+    pub fn bit_provenance(&self, idx: usize) -> Option<u16> {
+        self.provenance.get(&idx).copied()
+    }
+
+    /// Same as `union`, but returns an error for incompatible filters instead
+    /// of panicking
+    pub fn try_union(&mut self, other: &Self) -> Result<bool, BloomError> {
+        if !self.is_compatible_with(other) {
+            return Err(BloomError::IncompatibleParameters);
+        }
+        Ok(self.union(other))
+    }
+
+    /// Checks that set operations with `other` are possible, i.e. both filters
+    /// have the same size, k, fp, seeds and reduction
+    pub fn is_compatible_with(&self, other: &BloomFilter) -> bool {
+        self.size == other.size
+            && self.k == other.k
+            && self.fp == other.fp
+            && self.seeds == other.seeds
+            && self.reduction == other.reduction
+    }
+
+    /// Returns number of bits set in both `self` and `other`, which must have
+    /// the same size
+    pub fn shared_set_bits(&self, other: &BloomFilter) -> usize {
+        assert!(
+            self.size == other.size,
+            "Only filters with the same size can be compared"
+        );
+        self.array
+            .blocks()
+            .zip(other.array.blocks())
+            .map(|(a, b)| (a & b).count_ones() as usize)
+            .sum()
+    }
+
+    /// Estimates number of distinct items in the union of `self` and `other`
+    /// from the bits set in either of them, without building the union.
+    /// Both filters must have the same size, k and fp.
+    pub fn estimated_union_count(&self, other: &BloomFilter) -> f64 {
+        assert!(
+            self.is_compatible_with(other),
+            "Only filters with the same size, k and fp can be estimated"
+        );
+        let set_bits: usize = self
+            .array
+            .blocks()
+            .zip(other.array.blocks())
+            .map(|(a, b)| (a | b).count_ones() as usize)
+            .sum();
+        self.estimate_items(set_bits)
+    }
+
+    /// Calculates the union of all given filters, which must share the same
+    /// size, k and fp.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bloom::BloomFilter;
+    /// let mut a = BloomFilter::with_fp_size(0.1, 10);
+    /// let mut b = BloomFilter::with_fp_size(0.1, 10);
+    /// a.add(&1);
+    /// b.add(&2);
+    /// let u = BloomFilter::union_all(&[a, b]).unwrap();
+    /// assert!(u.get(&1) && u.get(&2));
+    /// ```
+    pub fn union_all(filters: &[BloomFilter]) -> Result<BloomFilter, BloomError> {
+        let (first, rest) = filters.split_first().ok_or(BloomError::NoFilters)?;
+        let mut result = first.clone();
+        for other in rest {
+            if !first.is_compatible_with(other) {
+                return Err(BloomError::IncompatibleParameters);
+            }
+            result.array.union(&other.array);
+            result.stored_items += other.stored_items;
+        }
+        Ok(result)
+    }
+
+    /// Same as `union_all`, but ORs filters into `base` as they arrive, e.g.
+    /// from `mpsc::Receiver::iter`, without collecting them first. Stops at the
+    /// first filter incompatible with `base`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bloom::BloomFilter;
+    /// use std::sync::mpsc;
+    /// let (tx, rx) = mpsc::channel();
+    /// for i in 0..3 {
+    ///     let mut f = BloomFilter::with_fp_size(0.1, 10);
+    ///     f.add(&i);
+    ///     tx.send(f).unwrap();
+    /// }
+    /// drop(tx);
+    /// let u = BloomFilter::union_from_iter(BloomFilter::with_fp_size(0.1, 10), rx.iter()).unwrap();
+    /// assert!((0..3).all(|i| u.get(&i)));
+    /// ```
+    pub fn union_from_iter(
+        base: BloomFilter,
+        iter: impl Iterator<Item = BloomFilter>,
+    ) -> Result<BloomFilter, BloomError> {
+        let mut result = base;
+        for other in iter {
+            if !result.is_compatible_with(&other) {
+                return Err(BloomError::IncompatibleParameters);
+            }
+            result.array.union(&other.array);
+            result.stored_items += other.stored_items;
+        }
+        Ok(result)
+    }
+
+    /// Folds the filter in half: bit `i` of the result is bit `i` or bit
+    /// `i + m/2` of `self`. Since `m/2` divides `m`, every index reduced mod `m`
+    /// lands on the same index mod `m/2`, so no item is lost, at the cost of a
+    /// higher fp. `k` is unchanged. With `Reduction::MultiplyShift` indices
+    /// halve instead, so bits `2i` and `2i + 1` are merged. Filters past 2^32
+    /// bits derive positions differently, so they can't be folded below that.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut f = bloom::BloomFilter::with_fp_size(0.01, 100);
+    /// f.add(&42);
+    /// if let Ok(folded) = f.fold() {
+    ///     assert!(folded.get(&42));
+    /// }
+    /// ```
+    pub fn fold(&self) -> Result<BloomFilter, BloomError> {
+        if self.size & 1 != 0 {
+            return Err(BloomError::LengthMismatch);
+        }
+        let size = self.size / 2;
+        let half = size * 8;
+        if self.bits() as u128 > WIDE_HASH_BITS && half as u128 <= WIDE_HASH_BITS {
+            return Err(BloomError::LengthMismatch);
+        }
+        let mut array = BitVec::from_elem(half, false);
+        for idx in 0..half {
+            let (a, b) = match self.reduction {
+                Reduction::Modulo => (idx, idx + half),
+                Reduction::MultiplyShift => (2 * idx, 2 * idx + 1),
+            };
+            if self.array[a] || self.array[b] {
+                array.set(idx, true);
+            }
+        }
+        Ok(Self {
+            array,
+            size,
+            k: self.k,
+            capacity: BloomFilter::calculate_capacity_from_fp_size(self.fp, size),
+            fp: self.fp,
+            stored_items: self.stored_items,
+            fp_cache: FpCache::default(),
+            seeds: self.seeds,
+            track_counts: self.track_counts,
+            reduction: self.reduction,
+            on_overflow: self.on_overflow,
+            // folding moves bits
+            #[cfg(feature = "provenance")]
+            provenance: HashMap::new(),
+        })
+    }
+
+    /// Calculates the intersection of two filters. This acts like the bitwise `and`
+    /// function.
+    ///
+    /// Sets `self` to the intersection of `self` and `other`. Both filters must have
+    /// the same length and k number. Returns `true` if `self` changed.
+    ///
+    pub fn intersect(&mut self, other: &Self) -> bool {
+        assert!(
+            self.is_compatible_with(other),
+            "Only filters with the same size, k and fp can be intersected"
+        );
+        self.array.intersect(&other.array)
+    }
+
+    /// Same as `intersect`, but returns an error for incompatible filters
+    /// instead of panicking
+    pub fn try_intersect(&mut self, other: &Self) -> Result<bool, BloomError> {
+        if !self.is_compatible_with(other) {
+            return Err(BloomError::IncompatibleParameters);
+        }
+        Ok(self.intersect(other))
+    }
+}
+
+impl Filter for BloomFilter {
+    fn add<I: Hash>(&mut self, item: I) {
+        BloomFilter::add(self, item)
+    }
+
+    fn contains<I: Hash>(&self, item: I) -> bool {
+        self.get(item)
+    }
+}
+
+/// Union of two filters as a new filter, see `BloomFilter::union`
+impl BitOr for &BloomFilter {
+    type Output = BloomFilter;
+
+    fn bitor(self, other: &BloomFilter) -> BloomFilter {
+        let mut result = self.clone();
+        result.union(other);
+        result
+    }
+}
+
+/// Intersection of two filters as a new filter, see `BloomFilter::intersect`
+impl BitAnd for &BloomFilter {
+    type Output = BloomFilter;
+
+    fn bitand(self, other: &BloomFilter) -> BloomFilter {
+        let mut result = self.clone();
+        result.intersect(other);
+        result
+    }
+}
+
+/// Zeroes the bit array, which can leak membership of the keys it was built
+/// from, before the memory is freed
+#[cfg(feature = "zeroize")]
+impl Drop for BloomFilter {
+    fn drop(&mut self) {
+        self.wipe();
+    }
+}
+
+impl<I: Hash> Extend<I> for BloomFilter {
+    fn extend<T: IntoIterator<Item = I>>(&mut self, iter: T) {
+        self.add_all(iter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[derive(Hash, Eq, PartialEq)]
+    struct TestItem {
+        a: u32,
+    }
+
+    fn add_and_check<F: Filter>(mut f: F) {
+        for i in 0..20 {
+            f.add(i);
+        }
+        assert!((0..20).all(|i| f.contains(i)));
+        assert!(!f.contains("absent"));
+    }
+
+    #[test]
+    fn filter_trait() {
+        add_and_check(BloomFilter::with_fp_size(0.01, 100));
+        add_and_check(dynamic::DynamicBloom::new(8, 0.01));
+        add_and_check(const_bloom::ConstBloom::<256>::with_expected(20));
+    }
+
+    #[test]
+    fn item_in_filter() {
+        let item = TestItem { a: 42 };
+        let mut f = BloomFilter::new(1, 1, 0.1);
+        f.add(&item);
+        assert!(f.get(&item));
+    }
+
+    #[test]
+    fn k_larger_than_bits() {
+        let mut f = BloomFilter::new(1, 8, 0.1);
+        assert_eq!(f.k, 8);
+        for i in 0..20 {
+            f.add(&TestItem { a: i });
+        }
+        assert!((0..20).all(|i| f.get(&TestItem { a: i })));
+
+        let f = BloomFilter::new(1, 20, 0.1);
+        assert_eq!(f.k, 8);
+        assert_eq!(f.positions(42).len(), 8);
+        let f = BloomFilter::from_bitvec(BitVec::from_elem(8, false), 20, 0.1);
+        assert_eq!(f.k, 8);
+    }
+
+    #[test]
+    fn false_negatives() {
+        let items = (0..64)
+            .map(|i| TestItem { a: i })
+            .collect::<Vec<TestItem>>();
+        let mut f = BloomFilter::new(8, 2, 0.1);
+        let _: () = items.iter().map(|i| f.add(i)).collect();
+        let mut rng = rand::thread_rng();
+        for _ in 0..128 {
+            let idx = rng.gen_range(0, items.len());
+            let item = &items[idx];
+            assert!(f.get(&item));
+        }
+    }
+
+    #[test]
+    fn false_positive() {
+        let items = (0..64)
+            .map(|i| TestItem { a: i })
+            .collect::<Vec<TestItem>>();
+        //let mut f = BloomFilter::new(427, 8);
+        let mut f = BloomFilter::with_fp_size(0.01, 64);
+
+        let _: () = items.iter().map(|i| f.add(i)).collect();
+        let mut rng = rand::thread_rng();
+        let false_items = (64..128)
+            .map(|i| TestItem { a: i })
+            .collect::<Vec<TestItem>>();
+
+        let mut positives = 0;
+        for _ in 0..128 {
+            let idx = rng.gen_range(0, false_items.len());
+            let item = &false_items[idx];
+            if f.get(&item) {
+                positives += 1;
+                assert!(!items.contains(&item));
+            }
+        }
+        dbg!(f.array.len());
+        dbg!(positives);
+        dbg!(f.k);
+        assert!(positives <= (false_items.len() / 10));
+    }
+
+    #[test]
+    fn chi_square_uniformity() {
+        let f = BloomFilter::new(128, 4, 0.01);
+        let sample: Vec<u32> = (0..20_000).collect();
+        let chi_square = f.chi_square_uniformity(&sample);
+        let df = (f.bits() - 1) as f64;
+        assert!((chi_square - df).abs() < 5.0 * (2.0 * df).sqrt());
+
+        // a filter where all items hash alike is far off
+        let same: Vec<u32> = vec![7; 20_000];
+        assert!(f.chi_square_uniformity(&same) > df * 100.0);
+    }
+
+    #[test]
+    fn measured_fp_matches_analytic() {
+        use rand::SeedableRng;
+
+        let mut f = BloomFilter::with_fp_size(0.01, 10_000);
+        for i in 0..f.capacity() {
+            f.add(i);
+        }
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let measured = f.measure_fp(100_000, &mut rng);
+        assert!(measured < f.current_fp() * 2.0);
+        assert!(measured > f.current_fp() / 2.0);
+    }
+
+    #[test]
+    fn target_and_current_fp() {
+        let mut f = BloomFilter::with_fp_size(0.01, 1000);
+        assert_eq!(f.current_fp(), 0.0);
+        let mut last = 0.0;
+        for i in 0..1000 {
+            f.add(i);
+            assert_eq!(f.target_fp(), 0.01);
+            assert!(f.current_fp() >= last);
+            last = f.current_fp();
+        }
+        assert!(last > 0.001 && last <= 0.01);
+        #[allow(deprecated)]
+        let fp = f.fp();
+        assert_eq!(fp, last);
+    }
+
+    #[test]
+    fn fp_changes_with_inserts() {
+        // we ask for a filter that should be able to hold 16 items with 0.1 false negative rate
+        // and filter will calc optimal k value
+        let mut f = BloomFilter::with_fp_size(0.1, 16);
+        println!("Current capacity/fp: {}/{:1.6}", f.capacity, f.current_fp());
+        // now insert 32 items which should drive fp up
+        for i in 0..32 {
+            f.add(&TestItem { a: i })
+        }
+        // it should become ~0.3
+        assert!(f.current_fp() >= 0.3f64);
+    }
+
+    #[test]
+    fn cached_fp_matches_computed() {
+        let mut f = BloomFilter::with_fp_size(0.1, 16);
+        for i in 0..32 {
+            f.add(&TestItem { a: i });
+            let computed = BloomFilter::calculate_fp_from_capacity_size(f.size, f.stored_items);
+            assert_eq!(f.current_fp(), computed);
+            // second call is served from cache
+            assert_eq!(f.current_fp(), computed);
+        }
+        assert_eq!(f.clone().current_fp(), f.current_fp());
+    }
+
+    #[test]
+    fn empty_filter_has_nothing() {
+        let f = BloomFilter::with_fp_size(0.1, 100);
+        assert!((0..1000).all(|i| !f.get(i)));
+        assert!(!f.get_bytes(b"key"));
+
+        let mut f = BloomFilter::new(16, 0, 0.1);
+        assert!(!f.get(42));
+        assert!(!f.get_and_add(42));
+    }
+
+    #[test]
+    fn shared_set_bits() {
+        let mut a = BloomFilter::with_fp_size(0.01, 1000);
+        let mut b = BloomFilter::with_fp_size(0.01, 1000);
+        let c = BloomFilter::with_fp_size(0.01, 1000);
+        for i in 0..500 {
+            a.add(i);
+            b.add(i + 400);
+        }
+        let shared = a.shared_set_bits(&b);
+        assert!(shared > 0);
+        assert!(shared < a.set_bits() && shared < b.set_bits());
+        assert_eq!(a.shared_set_bits(&a), a.set_bits());
+        assert_eq!(a.shared_set_bits(&c), 0);
+    }
+
+    #[test]
+    fn estimated_union_count() {
+        let mut a = BloomFilter::with_fp_size(0.01, 5000);
+        let mut b = BloomFilter::with_fp_size(0.01, 5000);
+        for i in 0..2000 {
+            a.add(i);
+        }
+        for i in 1000..4000 {
+            b.add(i);
+        }
+        // 0..4000
+        let estimate = a.estimated_union_count(&b);
+        assert!((estimate / 4000.0 - 1.0).abs() < 0.05);
+        assert!((a.estimated_union_count(&a) / 2000.0 - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    #[cfg(feature = "provenance")]
+    fn union_tracked() {
+        let mut merged = BloomFilter::with_fp_size(0.001, 100);
+        merged.add(0);
+        let mut shards = [
+            BloomFilter::with_fp_size(0.001, 100),
+            BloomFilter::with_fp_size(0.001, 100),
+        ];
+        shards[0].add(1);
+        shards[1].add(2);
+        shards[1].add(1);
+        for (id, shard) in shards.iter().enumerate() {
+            merged.union_tracked(shard, id as u16);
+        }
+        for idx in merged.positions(0) {
+            assert_eq!(merged.bit_provenance(idx), None);
+        }
+        let own = merged.positions(0);
+        for idx in merged.positions(1).into_iter().filter(|i| !own.contains(i)) {
+            assert_eq!(merged.bit_provenance(idx), Some(0));
+        }
+        let seen: Vec<usize> = own.into_iter().chain(merged.positions(1)).collect();
+        for idx in merged
+            .positions(2)
+            .into_iter()
+            .filter(|i| !seen.contains(i))
+        {
+            assert_eq!(merged.bit_provenance(idx), Some(1));
+        }
+        assert!(merged.get(2));
+    }
+
+    #[test]
+    fn bit_operators() {
+        let mut a = BloomFilter::with_fp_size(0.001, 100);
+        let mut b = BloomFilter::with_fp_size(0.001, 100);
+        a.add_all(0..20);
+        b.add_all(10..30);
+
+        let u = &a | &b;
+        assert!((0..30).all(|i| u.get(i)));
+        assert_eq!(u.stored(), 40);
+
+        let i = &a & &b;
+        assert!((10..20).all(|n| i.get(n)));
+        assert!((0..10).chain(20..30).filter(|n| i.get(n)).count() < 2);
+        // operands are untouched
+        assert!(!a.get(25) && !b.get(5));
+    }
+
+    #[test]
+    #[should_panic(expected = "can be unioned")]
+    fn bitor_panics_when_filters_are_different() {
+        let _ = &BloomFilter::new(16, 3, 0.1) | &BloomFilter::new(16, 4, 0.1);
+    }
+
+    #[test]
+    #[should_panic(expected = "can be intersected")]
+    fn bitand_panics_when_filters_are_different() {
+        let _ = &BloomFilter::new(16, 3, 0.1) & &BloomFilter::new(32, 3, 0.1);
+    }
+
+    #[test]
+    fn union_into_empty_filter() {
+        let mut a = BloomFilter::with_fp_size(0.1, 100);
+        let mut b = BloomFilter::with_fp_size(0.1, 100);
+        b.add(42);
+        b.add(43);
+        a.union(&b);
+        assert_eq!(a.stored(), 2);
+        assert!(a.get(42));
+    }
+
+    #[test]
+    fn track_counts_can_be_disabled() {
+        let mut f = BloomFilter::with_fp_size(0.01, 1000).with_track_counts(false);
+        for i in 0..500 {
+            f.add(i);
+        }
+        assert_eq!(f.stored(), 0);
+        assert!((0..500).all(|i| f.get(i)));
+        assert!(f.try_add(500).is_ok());
+        let occupancy_fp = f.fill_ratio().powi(f.k as i32);
+        assert_eq!(f.current_fp(), occupancy_fp);
+        assert!(f.current_fp() > 0.0 && f.current_fp() < 0.01);
+
+        f.set_track_counts(true);
+        assert!((f.stored() as f64 - 501.0).abs() < 25.0);
+        f.add(501);
+        assert!((f.stored() as f64 - 502.0).abs() < 25.0);
+    }
+
+    #[test]
+    fn stored_items_changed() {
+        let mut f = BloomFilter::with_fp_size(0.1, 16);
+        f.add(&TestItem { a: 42 });
+        assert!(f.stored_items == 1);
+    }
+
+    #[test]
+    fn calc_k() {
+        let k = BloomFilter::calculate_k(512, 5000);
+        assert_eq!(k, 1);
+    }
+
+    #[test]
+    fn calculate_capacity_from_fp_size() {
+        let capacity = BloomFilter::calculate_capacity_from_fp_size(0.01, 512);
+        assert_eq!(capacity, 427);
+    }
+
+    #[test]
+    fn calculate_fp_from_capacity_size() {
+        let fp = BloomFilter::calculate_fp_from_capacity_size(512, 5000) * 1_000_000.0;
+        assert_eq!(fp.round(), 674633.0);
+    }
+
+    #[test]
+    fn calculate_size_from_fp_capacity() {
+        let size = BloomFilter::calculate_size_from_fp_capacity(0.001, 5000);
+        assert_eq!(size, 8986);
+    }
+
+    #[test]
+    fn with_bits_and_items() {
+        let f = BloomFilter::with_bits_and_items(9585, 1000);
+        assert_eq!(f.bits(), 9592);
+        assert_eq!(f.k, BloomFilter::calculate_k(1199, 1000) as usize);
+        assert_eq!(
+            f.target_fp(),
+            BloomFilter::calculate_fp_from_capacity_size(1199, 1000)
+        );
+        assert!(f.capacity() >= 999);
+    }
+
+    #[test]
+    fn with_bits_and_items_for_tiny_bits() {
+        let mut f = BloomFilter::with_bits_and_items(64, 1000);
+        assert_eq!(f.k, 1);
+        f.add(1);
+        assert!(f.get(1));
+        assert!((2..100).any(|i| !f.get(i)));
+    }
+
+    #[test]
+    fn from_bitvec() {
+        let layout = BloomFilter::new(128, 4, 0.01);
+        let mut array = BitVec::from_elem(1024, false);
+        for i in 0..10 {
+            for idx in layout.positions(i) {
+                array.set(idx, true);
+            }
+        }
+        let f = BloomFilter::from_bitvec(array, 4, 0.01);
+        assert_eq!(f.size, 128);
+        assert_eq!(f.capacity(), layout.capacity());
+        assert!((f.stored() as i64 - 10).abs() <= 1);
+        assert!((0..10).all(|i| f.get(i)));
+        assert!((10..20).filter(|i| f.get(i)).count() < 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "multiple of 8")]
+    fn from_bitvec_of_odd_length() {
+        BloomFilter::from_bitvec(BitVec::from_elem(1001, false), 4, 0.01);
+    }
+
+    #[test]
+    fn for_workload() {
+        let optimal = BloomFilter::with_fp_size(0.001, 1000);
+        let write_heavy = BloomFilter::for_workload(0.001, 1000, 0.0);
+        let read_heavy = BloomFilter::for_workload(0.001, 1000, 1000.0);
+        assert!(write_heavy.k < optimal.k);
+        assert!(write_heavy.bits() > optimal.bits());
+        assert!(read_heavy.k >= write_heavy.k);
+
+        for f in [write_heavy, read_heavy].iter() {
+            // fp = (1 - e^(-kn/m))^k
+            let (k, m) = (f.k as f64, f.bits() as f64);
+            let fp = (1.0 - (-k * 1000.0 / m).exp()).powf(k);
+            assert!(fp <= 0.001);
+            // capacity is the one of the chosen k
+            let n = f.capacity() as f64;
+            assert!((n - 1000.0).abs() < 10.0);
+            assert!((1.0 - (-k * n / m).exp()).powf(k) <= 0.001);
+            assert_eq!(f.validate(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn with_fp_max_k() {
+        let optimal = BloomFilter::with_fp_size(0.0001, 1000);
+        let capped = BloomFilter::with_fp_max_k(0.0001, 1000, 4);
+        assert_eq!(capped.k, 4);
+        assert!(capped.bits() > optimal.bits());
+        let (k, m) = (capped.k as f64, capped.bits() as f64);
+        let fp = (1.0 - (-k * 1000.0 / m).exp()).powf(k);
+        assert!(fp <= 0.0001);
+
+        // capacity is the one of 4 hashes, not of the optimal k
+        let n = capped.capacity() as f64;
+        assert!((n - 1000.0).abs() < 10.0);
+        assert!((1.0 - (-k * n / m).exp()).powf(k) <= 0.0001);
+        let mut full = BloomFilter::with_fp_max_k(0.0001, 1000, 4);
+        full.add_range(0..full.capacity());
+        assert!(full.current_fp() <= 0.0001);
+        assert_eq!(full.validate(), Ok(()));
+
+        // no cap needed
+        let loose = BloomFilter::with_fp_max_k(0.0001, 1000, 20);
+        assert_eq!(loose.k, optimal.k);
+        assert_eq!(loose.bits(), optimal.bits());
+    }
+
+    #[test]
+    fn from_set() {
+        let items = (0..1000).collect::<HashSet<u32>>();
+        let f = BloomFilter::from_set(&items, 0.01);
+        assert_eq!(f.stored(), 1000);
+        assert!(items.iter().all(|i| f.get(i)));
+
+        let probes = 10_000;
+        let positives = (1000..1000 + probes).filter(|i| f.get(i)).count();
+        assert!((positives as f64 / probes as f64) < 0.02);
+    }
+
+    #[test]
+    fn rebuild_retaining() {
+        let items: Vec<u32> = (0..1000).collect();
+        let f = BloomFilter::rebuild_retaining(&items, |i| i % 2 == 0, 0.01);
+        assert_eq!(f.stored(), 500);
+        assert_eq!(
+            f.capacity(),
+            BloomFilter::with_fp_size(0.01, 500).capacity()
+        );
+        assert!(items.iter().filter(|i| *i % 2 == 0).all(|i| f.get(i)));
+        let odds = items.iter().filter(|i| *i % 2 == 1 && f.get(i)).count();
+        assert!(odds < 20);
+
+        let none = BloomFilter::rebuild_retaining(&items, |_| false, 0.01);
+        assert!(!none.get(0));
+    }
+
+    #[test]
+    fn build_from_iter() {
+        let f = BloomFilter::build(0.01, 100, (0..100).map(|i| i * 2)).unwrap();
+        assert_eq!(f.stored(), 100);
+        assert!((0..100).all(|i| f.get(i * 2)));
+
+        let err = BloomFilter::build(0.01, 100, 0..101).err();
+        assert_eq!(err, Some(CapacityExceeded));
+    }
+
+    #[test]
+    fn salted_filters_are_independent() {
+        let mut a = BloomFilter::with_fp_size(0.01, 1000).with_salt(1);
+        let mut b = BloomFilter::with_fp_size(0.01, 1000).with_salt(2);
+        let mut c = BloomFilter::with_fp_size(0.01, 1000).with_salt(1);
+        for i in 0..1000 {
+            a.add(i);
+            b.add(i);
+            c.add(i);
+        }
+        assert_eq!(a.shared_set_bits(&c), a.set_bits());
+        // independent filters share bits by chance only
+        let baseline = a.set_bits() as f64 * b.set_bits() as f64 / a.bits() as f64;
+        let overlap = a.shared_set_bits(&b) as f64;
+        assert!((overlap / baseline - 1.0).abs() < 0.1);
+
+        assert!(!a.is_compatible_with(&b));
+        assert!(a.is_compatible_with(&c));
+    }
+
+    #[test]
+    fn seeded_filters_are_reproducible() {
+        let fill = |seeds| {
+            let mut f = BloomFilter::with_seeds(1024, 5, 0.01, seeds);
+            for i in 0..100 {
+                f.add(i);
+                f.add_hash(i);
+            }
+            f
+        };
+        let a = fill([1, 2]);
+        assert_eq!(a.seeds(), [1, 2]);
+        assert!(a.array == fill([1, 2]).array);
+        assert!(a.array != fill([2, 1]).array);
+        assert!(a.array != fill([1, 3]).array);
+        // equal seeds are a salt
+        let mut salted = BloomFilter::new(1024, 5, 0.01).with_salt(7);
+        for i in 0..100 {
+            salted.add(i);
+            salted.add_hash(i);
+        }
+        assert!(fill([7, 7]).array == salted.array);
+    }
+
+    #[test]
+    #[should_panic(expected = "empty filter")]
+    fn salt_of_non_empty_filter() {
+        let mut f = BloomFilter::with_fp_size(0.01, 1000);
+        f.add(42);
+        f.with_salt(1);
+    }
+
+    #[test]
+    fn best_fp_for() {
+        let mut f = BloomFilter::with_fp_size(0.01, 1000);
+        for i in 0..1000 {
+            f.add(&TestItem { a: i });
+        }
+        assert_eq!(BloomFilter::best_fp_for(f.size, 1000), f.current_fp());
+        assert!(BloomFilter::best_fp_for(f.size, 1000) <= 0.01);
+        // less space means worse accuracy
+        assert!(BloomFilter::best_fp_for(f.size / 2, 1000) > f.current_fp());
+    }
+
+    #[test]
+    fn try_add_up_to_capacity() {
+        let mut f = BloomFilter::with_fp_size(0.1, 16);
+        for i in 0..f.capacity() as u32 {
+            assert!(f.try_add(&TestItem { a: i }).is_ok());
+        }
+        let bits = f.array.clone();
+        assert_eq!(f.try_add(&TestItem { a: 1000 }), Err(CapacityExceeded));
+        assert_eq!(f.stored(), f.capacity());
+        assert!(f.array == bits);
+    }
+
+    #[test]
+    fn try_add_past_capacity_without_counts() {
+        let mut f = BloomFilter::with_fp_size(0.1, 16);
+        for i in 0..f.capacity() as u32 {
+            f.add(&TestItem { a: i });
+        }
+        f.set_track_counts(false);
+        assert!(f.try_add(&TestItem { a: 1000 }).is_ok());
+        assert!(f.get(&TestItem { a: 1000 }));
+    }
+
+    #[test]
+    fn overflow_policies() {
+        let fill = |policy| {
+            let mut f = BloomFilter::with_fp_size(0.1, 16).with_overflow_policy(policy);
+            for i in 0..f.capacity() as u32 {
+                f.add(&TestItem { a: i });
+            }
+            f
+        };
+
+        let mut ignore = fill(OverflowPolicy::Ignore);
+        ignore.add(&TestItem { a: 1000 });
+        assert_eq!(ignore.stored(), ignore.capacity() + 1);
+        assert!(ignore.get(&TestItem { a: 1000 }));
+
+        let mut reject = fill(OverflowPolicy::Reject);
+        let bits = reject.array.clone();
+        reject.add(&TestItem { a: 1000 });
+        assert_eq!(reject.stored(), reject.capacity());
+        assert!(reject.array == bits);
+        assert_eq!(reject.try_add(&TestItem { a: 1000 }), Err(CapacityExceeded));
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity exceeded")]
+    fn overflow_policy_panic() {
+        let mut f = BloomFilter::with_fp_size(0.1, 16).with_overflow_policy(OverflowPolicy::Panic);
+        for i in 0..f.capacity() as u32 {
+            f.add(&TestItem { a: i });
+        }
+        assert_eq!(f.stored(), f.capacity());
+        f.add(&TestItem { a: 1000 });
+    }
+
+    #[test]
+    fn compute_hashes_len_is_k() {
+        for k in 1..=64 {
+            let f = BloomFilter::new(128, k, 0.1);
+            assert_eq!(f.compute_hashes(&42).len(), k);
+            assert_eq!(f.positions(42).len(), k);
+        }
+    }
+
+    #[test]
+    fn effective_k_is_close_to_k() {
+        let f = BloomFilter::with_fp_size(0.01, 10_000);
+        let sample: Vec<u32> = (0..1000).collect();
+        let effective = f.effective_k(&sample);
+        assert!(effective <= f.k as f64);
+        assert!(effective > f.k as f64 - 0.05);
+    }
+
+    #[test]
+    fn self_check() {
+        let f = BloomFilter::with_fp_size(0.001, 1000);
+        assert!(f.self_check().is_ok());
+        assert!(BloomFilter::new(1, 3, 0.1).self_check().is_ok());
+        // tiny filters repeat bits by construction, e.g. 17 hashes over 24 bits
+        for fp in [0.3, 0.1, 0.01, 0.0001].iter() {
+            for expected in 1..10 {
+                let f = BloomFilter::with_fp_size(*fp, expected);
+                assert_eq!(f.self_check(), Ok(()), "fp {} expected {}", fp, expected);
+            }
+        }
+
+        // equal base hashes would be fine, a zero stride is not: every hash
+        // past the first one lands on bit 0
+        let broken = |item: u64| [item as u128 * 7919, 0];
+        let err = f.check_hashing(broken).unwrap_err();
+        assert!(err.effective_k <= 2.0);
+        assert!(err.expected_k > 9.0);
+    }
+
+    #[test]
+    fn validate() {
+        // single item filters get k for 1 item but capacity for a whole byte
+        for fp in [0.5, 0.3, 0.1, 0.01, 0.0001].iter() {
+            for expected in [10, 1000, 100_000].iter() {
+                assert_eq!(BloomFilter::with_fp_size(*fp, *expected).validate(), Ok(()));
+            }
+        }
+        assert_eq!(BloomFilter::new(1, 3, 0.1).validate(), Ok(()));
+
+        // a single hash can't give 0.0001 with this few bits per item
+        let f = BloomFilter::from_bitvec(BitVec::from_elem(1024, false), 1, 0.0001);
+        match f.validate() {
+            Err(ValidationError::FpMismatch { target, computed }) => {
+                assert_eq!(target, 0.0001);
+                assert!(computed > 0.01);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let mut f = BloomFilter::with_fp_size(0.01, 100);
+        f.capacity *= 2;
+        assert_eq!(
+            f.validate(),
+            Err(ValidationError::CapacityMismatch {
+                recorded: f.capacity,
+                computed: f.capacity / 2,
+            })
+        );
+    }
+
+    #[test]
+    fn positions_are_set_by_add() {
+        let mut f = BloomFilter::with_fp_size(0.01, 100);
+        let item = TestItem { a: 42 };
+        let positions = f.positions(&item);
+        assert_eq!(positions.len(), f.k);
+        assert!(positions.iter().all(|&idx| idx < f.bits()));
+
+        f.add(&item);
+        let mut distinct = positions.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+        assert_eq!(f.set_bits(), distinct.len());
+        assert!(positions.iter().all(|&idx| f.array[idx]));
+    }
+
+    #[test]
+    fn bytes_path_is_consistent() {
+        let keys = (0..100u32)
+            .map(|i| {
+                let mut key = [0u8; 32];
+                key[..4].copy_from_slice(&i.to_le_bytes());
+                key
+            })
+            .collect::<Vec<[u8; 32]>>();
+        let mut raw = BloomFilter::with_fp_size(0.01, 100);
+        let mut generic = BloomFilter::with_fp_size(0.01, 100);
+        for key in keys.iter() {
+            raw.add_bytes(key);
+            generic.add(&key[..]);
+        }
+        for key in keys.iter() {
+            assert!(raw.get_bytes(key));
+            assert!(generic.get(&key[..]));
+        }
+        // `Hash` adds a length prefix, so the two paths map keys differently
+        assert_ne!(
+            BloomFilter::base_hashes_bytes(&keys[0], [0, 0]),
+            BloomFilter::base_hashes(&&keys[0][..], [0, 0])
+        );
+    }
+
+    #[test]
+    fn fingerprint() {
+        let mut a = BloomFilter::with_fp_size(0.01, 100);
+        let mut b = BloomFilter::with_fp_size(0.01, 100);
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        for i in 0..20 {
+            a.add(i);
+            b.add(i);
+        }
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        b.add(20);
+        assert_ne!(a.fingerprint(), b.fingerprint());
+        assert_ne!(
+            BloomFilter::new(16, 3, 0.1).fingerprint(),
+            BloomFilter::new(16, 4, 0.1).fingerprint()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "stats-json")]
+    fn stats_json() {
+        let mut f = BloomFilter::with_fp_size(0.01, 1000);
+        f.add_all(0..500);
+        let json: serde_json::Value = serde_json::from_str(&f.stats_json()).unwrap();
+        let keys: Vec<&String> = json.as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["fp", "items", "k", "partitions", "size"]);
+        assert_eq!(json["size"].as_u64(), Some(f.size as u64));
+        assert_eq!(json["k"].as_u64(), Some(f.k as u64));
+        assert_eq!(json["items"].as_u64(), Some(500));
+        assert_eq!(json["fp"].as_f64(), Some(f.current_fp()));
+        assert_eq!(json["partitions"].as_u64(), Some(1));
+    }
+
+    #[test]
+    fn describe() {
+        let mut f = BloomFilter::with_fp_size(0.01, 1000);
+        f.add_all(0..500);
+        let description = f.describe();
+        let fields: Vec<(&str, &str)> = description
+            .split(", ")
+            .map(|field| {
+                let mut kv = field.split('=');
+                (kv.next().unwrap(), kv.next().unwrap())
+            })
+            .collect();
+        let names: Vec<&str> = fields.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["m", "k", "fp_target", "fp_now", "n", "fill"]);
+        let value = |i: usize| fields[i].1.parse::<f64>().unwrap();
+        assert_eq!(value(0), f.bits() as f64);
+        assert_eq!(value(1), f.k as f64);
+        assert_eq!(value(2), 0.01);
+        assert!(value(3) > 0.0 && value(3) < 0.01);
+        assert_eq!(value(4), 500.0);
+        assert!(value(5) > 0.2 && value(5) < 0.5);
+    }
+
+    #[test]
+    fn expected_set_bits() {
+        let mut f = BloomFilter::with_fp_size(0.01, 2000);
+        assert_eq!(f.expected_set_bits(), 0.0);
+        for i in 0..2000 {
+            f.add(&TestItem { a: i });
+        }
+        let expected = f.expected_set_bits();
+        assert!((f.set_bits() as f64 - expected).abs() / expected < 0.03);
+    }
+
+    #[test]
+    fn word_popcount_histogram_skips_partial_word() {
+        // one 64-bit word and a 32-bit tail
+        let mut f = BloomFilter::new(12, 1, 0.1);
+        f.array.set_all();
+        let histogram = f.word_popcount_histogram();
+        assert_eq!(histogram.iter().sum::<u32>(), 1);
+        assert_eq!(histogram[64], 1);
+    }
+
+    #[test]
+    fn word_popcount_histogram_is_binomial() {
+        let mut f = BloomFilter::new(8192, 7, 0.01);
+        let n = f.items_until_fill(0.5);
+        for i in 0..n {
+            f.add(i);
+        }
+        let histogram = f.word_popcount_histogram();
+        assert_eq!(histogram.len(), 65);
+        let words: u32 = histogram.iter().sum();
+        assert_eq!(words, 1024);
+
+        // binomial(64, p) has mean 64p and variance 64p(1 - p)
+        let p = f.fill_ratio();
+        let mean = histogram
+            .iter()
+            .enumerate()
+            .map(|(ones, count)| ones as f64 * *count as f64)
+            .sum::<f64>()
+            / words as f64;
+        let variance = histogram
+            .iter()
+            .enumerate()
+            .map(|(ones, count)| (ones as f64 - mean).powi(2) * *count as f64)
+            .sum::<f64>()
+            / words as f64;
+        assert!((mean - 64.0 * p).abs() < 1e-9);
+        let expected_variance = 64.0 * p * (1.0 - p);
+        assert!((variance / expected_variance - 1.0).abs() < 0.25);
+    }
+
+    #[test]
+    fn items_until_fill() {
+        let mut f = BloomFilter::with_fp_size(0.01, 1000);
+        let n = f.items_until_fill(0.5);
+        for i in 0..n as u32 {
+            f.add(&TestItem { a: i });
+        }
+        assert!((f.fill_ratio() - 0.5).abs() < 0.03);
+        assert_eq!(f.items_until_fill(0.5), 0);
+    }
+
+    #[test]
+    fn fp_model_drift() {
+        let mut f = BloomFilter::with_fp_size(0.01, 10_000);
+        assert_eq!(f.fp_model_drift(), 1.0);
+        f.add_all(0..10_000u32);
+        assert!((f.fp_model_drift() - 1.0).abs() < 0.2);
+
+        let mut dup = BloomFilter::with_fp_size(0.01, 10_000);
+        for i in 0..10_000u32 {
+            dup.add(i % 1000);
+        }
+        assert!(dup.fp_model_drift() < 0.01);
+    }
+
+    #[test]
+    #[cfg(feature = "zeroize")]
+    fn zeroize_on_drop() {
+        let mut f = BloomFilter::with_fp_size(0.01, 100);
+        f.add_all(0..100);
+        // dropping a clone leaves the original intact
+        drop(f.clone());
+        assert!((0..100).all(|i| f.get(i)));
+
+        let bits = f.bits();
+        f.wipe();
+        assert_eq!(f.set_bits(), 0);
+        assert_eq!(f.bits(), bits);
+    }
+
+    #[test]
+    fn approx_fill_ratio() {
+        let mut f = BloomFilter::with_fp_size(0.01, 100_000);
+        f.add_all(0..60_000u32);
+        let exact = f.fill_ratio();
+        let approx = f.approx_fill_ratio(1024);
+        assert!((approx - exact).abs() < exact * 0.03);
+        assert_eq!(f.approx_fill_ratio(usize::MAX), exact);
+
+        // every 4th word full, a stride of 4 words would only see those
+        let bytes: Vec<u8> = (0..16_384)
+            .map(|i| if (i / 4) % 4 == 0 { 0xff } else { 0 })
+            .collect();
+        let f = BloomFilter::from_bitvec(BitVec::from_bytes(&bytes), 3, 0.01);
+        assert_eq!(f.fill_ratio(), 0.25);
+        assert!((f.approx_fill_ratio(1024) - 0.25).abs() < 0.06);
+    }
+
+    #[test]
+    fn add_all_streams_lines() {
+        use std::io::{BufRead, Cursor};
+
+        let data: String = (0..10_000).map(|i| format!("line-{}\n", i)).collect();
+        let mut f = BloomFilter::with_fp_size(0.01, 10_000);
+        f.add_all(Cursor::new(data.as_bytes()).lines().map(|l| l.unwrap()));
+        assert_eq!(f.stored(), 10_000);
+        assert!((0..10_000).all(|i| f.get(format!("line-{}", i))));
+
+        let mut g = BloomFilter::with_fp_size(0.01, 10_000);
+        g.extend(data.lines());
+        assert_eq!(g.array, f.array);
+    }
+
+    #[test]
+    fn u64_fast_path_matches_hash() {
+        let mut a = BloomFilter::with_fp_size(0.01, 1000);
+        let mut b = BloomFilter::with_fp_size(0.01, 1000);
+        for v in 0..1000u64 {
+            a.add_u64(v * 7919);
+            b.add(v * 7919);
+        }
+        assert_eq!(a.array, b.array);
+        assert!((0..1000u64).all(|v| a.get_u64(v * 7919) && a.get(v * 7919)));
+    }
+
+    #[test]
+    fn u128_fast_path_matches_hash() {
+        let mut a = BloomFilter::with_fp_size(0.01, 1000);
+        let mut b = BloomFilter::with_fp_size(0.01, 1000);
+        let key = |v: u128| v.wrapping_mul(0x9e3779b97f4a7c15f39cc0605cedc835);
+        for v in 0..1000 {
+            a.add_u128(key(v));
+            b.add(key(v));
+        }
+        assert_eq!(a.array, b.array);
+        assert!((0..1000).all(|v| a.get_u128(key(v)) && a.get(key(v))));
+        assert!(!a.get_u128(key(5000)));
+    }
+
+    #[test]
+    fn add_range() {
+        let mut f = BloomFilter::with_fp_size(0.001, 10_000);
+        f.add_range(0..10_000);
+        assert_eq!(f.stored(), 10_000);
+        for v in (0..10_000).step_by(97) {
+            assert!(f.get_u64(v) && f.get(v));
+        }
+        assert!(f.get_range_any(9_990..20_000));
+        assert!(!f.get_range_any(1_000_000..1_000_010));
+        assert!(!f.get_range_any(5..5));
+    }
+
+    pub(crate) mod allocations {
+        use super::*;
+        use std::alloc::{GlobalAlloc, Layout, System};
+        use std::cell::Cell;
+
+        struct CountingAlloc;
+
+        thread_local! {
+            static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+        }
+
+        unsafe impl GlobalAlloc for CountingAlloc {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                ALLOCATIONS.with(|n| n.set(n.get() + 1));
+                System.alloc(layout)
+            }
+
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                System.dealloc(ptr, layout)
+            }
+        }
+
+        #[global_allocator]
+        static ALLOC: CountingAlloc = CountingAlloc;
+
+        /// Returns number of allocations made by the current thread so far
+        pub(crate) fn count() -> usize {
+            ALLOCATIONS.with(|n| n.get())
+        }
+
+        #[test]
+        fn add_and_get_do_not_allocate_after_warm_up() {
+            let mut f = BloomFilter::with_fp_size(0.01, 1000);
+            f.warm_up(16);
+            let before = count();
+            f.add(42);
+            f.add_bytes(b"key");
+            f.add_u64(7);
+            f.add_u128(7);
+            assert!(f.get(42) && f.get_bytes(b"key") && f.get_u64(7) && f.get_u128(7));
+            assert_eq!(count(), before);
+        }
+    }
+
+    #[test]
+    fn get_and_add() {
+        let mut f = BloomFilter::with_fp_size(0.01, 100);
+        for i in 0..50 {
+            let item = TestItem { a: i % 25 };
+            let before = f.get(&item);
+            assert_eq!(f.get_and_add(&item), before);
+            assert!(f.get(&item));
+        }
+        assert_eq!(f.stored(), 50);
+    }
+
+    #[test]
+    fn matching_bits_counts_all_k() {
+        let mut f = BloomFilter::with_fp_size(0.01, 100);
+        f.add_all(0..100);
+        assert_eq!(f.matching_bits(42), f.k);
+        for i in 1000..2000 {
+            let set = f
+                .positions(i)
+                .into_iter()
+                .filter(|&idx| f.array[idx])
+                .count();
+            assert_eq!(f.matching_bits(i), set);
+            assert_eq!(f.get(i), set == f.k);
+        }
+    }
+
+    #[test]
+    fn get_with_confidence() {
+        let mut f = BloomFilter::with_fp_size(0.01, 100);
+        f.add_all(0..100);
+        let absent = (1000..).find(|i| !f.get(i)).unwrap();
+        assert_eq!(f.get_with_confidence(absent), None);
+        let confidence = f.get_with_confidence(42).unwrap();
+        assert!(confidence > 0.0 && confidence <= 1.0);
+        assert_eq!(confidence, 1.0 - f.current_fp());
+    }
+
+    #[test]
+    fn get_many() {
+        let mut f = BloomFilter::with_fp_size(0.1, 100);
+        for i in 0..100 {
+            f.add(i * 2);
+        }
+        // crosses several blocks and ends in a partial one
+        let items: Vec<u32> = (0..400).collect();
+        let found = f.get_many(&items);
+        assert_eq!(found.len(), items.len());
+        for (item, found) in items.iter().zip(found.iter()) {
+            assert_eq!(found, f.get(item));
+        }
+        assert!(f.get_many::<u32>(&[]).is_empty());
+    }
+
+    #[test]
+    fn get_fraction() {
+        let signature: Vec<u64> = (0..64).map(|i| i * 1_000_003).collect();
+        let mut f = BloomFilter::with_fp_size(0.0001, 64);
+        f.add_all(signature.iter());
+        assert_eq!(f.get_fraction(&signature), 1.0);
+
+        // 48 of 64 sub-hashes shared
+        let similar: Vec<u64> = signature[..48]
+            .iter()
+            .cloned()
+            .chain((0..16).map(|i| i * 7 + 1))
+            .collect();
+        assert!((f.get_fraction(&similar) - 0.75).abs() < 0.02);
+    }
+
+    #[test]
+    fn any_in_range() {
+        let mut f = BloomFilter::with_fp_size(0.001, 100);
+        for bucket in [3u64, 40, 41].iter() {
+            f.add(("cpu", *bucket));
+        }
+        let key = |bucket| ("cpu", bucket);
+        assert!(f.any_in_range(0..10, key));
+        assert!(f.any_in_range(35..45, key));
+        assert!(!f.any_in_range(4..40, key));
+        assert!(!f.any_in_range(0..10, |bucket| ("mem", bucket)));
+        assert!(!f.any_in_range(3..3, key));
+    }
+
+    #[test]
+    fn hashed_by_tenant_key() {
+        use std::collections::hash_map::RandomState;
+        use std::hash::BuildHasher;
+
+        let tenant_a = RandomState::new();
+        let tenant_b = RandomState::new();
+        let mut f = BloomFilter::with_fp_size(0.001, 1000);
+        for i in 0..500 {
+            f.add_hashed_by(&TestItem { a: i }, tenant_a.build_hasher());
+        }
+        assert!((0..500).all(|i| f.get_hashed_by(&TestItem { a: i }, tenant_a.build_hasher())));
+        let leaked = (0..500)
+            .filter(|i| f.get_hashed_by(&TestItem { a: *i }, tenant_b.build_hasher()))
+            .count();
+        assert!(leaked < 5);
+    }
+
+    #[test]
+    fn add_with_key_fn() {
+        use std::collections::hash_map::DefaultHasher;
+
+        struct Record {
+            id: u32,
+            payload: &'static str,
+        }
+        let key = |r: &Record| {
+            let mut s = DefaultHasher::new();
+            r.id.hash(&mut s);
+            s.finish()
+        };
+
+        let a = Record {
+            id: 1,
+            payload: "a",
+        };
+        let b = Record {
+            id: 1,
+            payload: "b",
+        };
+        let c = Record {
+            id: 2,
+            payload: "a",
+        };
+        assert_ne!(a.payload, b.payload);
+
+        let mut f = BloomFilter::with_fp_size(0.01, 100);
+        f.add_with(&a, key);
+        assert!(f.get_with(&b, key));
+        assert!(!f.get_with(&c, key));
+        assert_eq!(f.stored(), 1);
+    }
+
+    #[test]
+    fn contains_hashes() {
+        use std::collections::hash_map::DefaultHasher;
+
+        let hash_of = |item: &u32| {
+            let mut s = DefaultHasher::new();
+            item.hash(&mut s);
+            s.finish()
+        };
+        let mut f = BloomFilter::with_fp_size(0.01, 100);
+        for i in 0..50u32 {
+            f.add_with(&i, hash_of);
+        }
+        let present: Vec<u64> = (0..50).map(|i| hash_of(&i)).collect();
+        assert!(f.contains_hashes(&present).into_iter().all(|found| found));
+        let absent: Vec<u64> = (1000..1050).map(|i| hash_of(&i)).collect();
+        assert!(
+            f.contains_hashes(&absent)
+                .into_iter()
+                .filter(|found| *found)
+                .count()
+                < 3
+        );
+
+        f.add_hash(present[0] ^ 1);
+        assert_eq!(f.contains_hashes(&[present[0] ^ 1]), vec![true]);
+    }
+
+    #[test]
+    #[ignore]
+    #[cfg(target_pointer_width = "64")]
+    fn uniform_positions_in_huge_filter() {
+        // 2^34 bits (2 GiB), run with `cargo test --release -- --ignored`
+        let f = BloomFilter::new(1 << 31, 4, 0.01);
+        let regions = 16;
+        let region_bits = f.bits() / regions;
+        let mut counts = vec![0u64; regions];
+        let items = 200_000u32;
+        for i in 0..items {
+            for idx in f.positions(i) {
+                counts[idx / region_bits] += 1;
+            }
+        }
+        let expected = (items as usize * f.k) as f64 / regions as f64;
+        for count in counts {
+            assert!((count as f64 - expected).abs() / expected < 0.02);
+        }
+    }
+
+    #[test]
+    #[cfg(all(
+        feature = "fasthash",
+        not(any(feature = "ahash", feature = "portable-hash"))
+    ))]
+    fn scratch_hashing_matches_hashers() {
+        fn check<I: Hash>(item: I) {
+            for seeds in [[0, 0], [1, 2], [u64::MAX, 7]].iter() {
+                let expected = [
+                    BloomFilter::_mmr3_hash(&item, seeds[0]),
+                    BloomFilter::_xxh3_hash(&item, seeds[1]),
+                ];
+                assert_eq!(BloomFilter::base_hashes(&item, *seeds), expected);
+            }
+        }
+        check(42u32);
+        check("key");
+        check((7u64, "tenant", [1u8, 2, 3]));
+        check(vec![0u8; 1000]);
+        // a short item after a long one, the buffer is cleared in between
+        check(String::from("a"));
+    }
+
+    #[test]
+    #[cfg(all(
+        feature = "fasthash",
+        not(any(feature = "ahash", feature = "portable-hash"))
+    ))]
+    fn scratch_buffer_is_capped() {
+        let mut f = BloomFilter::with_fp_size(0.01, 100);
+        let huge = vec![7u8; 4 * MAX_SCRATCH_BYTES];
+        f.add(&huge);
+        assert!(f.get(&huge));
+        let capacity = HASH_SCRATCH.with(|scratch| scratch.borrow().capacity());
+        assert!(capacity <= MAX_SCRATCH_BYTES);
+    }
+
+    #[test]
+    fn hashing_from_thread_local_destructor() {
+        use std::cell::RefCell;
+        use std::sync::atomic::AtomicBool;
+
+        static CHECKED: AtomicBool = AtomicBool::new(false);
+
+        struct LookupOnDrop(BloomFilter);
+
+        impl Drop for LookupOnDrop {
+            fn drop(&mut self) {
+                CHECKED.store(self.0.get(42), Ordering::SeqCst);
+            }
+        }
+
+        thread_local! {
+            static GUARD: RefCell<Option<LookupOnDrop>> = const { RefCell::new(None) };
+        }
+
+        std::thread::spawn(|| {
+            // registered before the hashing buffer, so destroyed after it
+            let f = BloomFilter::with_fp_size(0.01, 100);
+            GUARD.with(|g| *g.borrow_mut() = Some(LookupOnDrop(f)));
+            GUARD.with(|g| g.borrow_mut().as_mut().unwrap().0.add(42));
+        })
+        .join()
+        .unwrap();
+        assert!(CHECKED.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    #[cfg(all(feature = "ahash", not(feature = "portable-hash")))]
+    fn ahash_filter_is_correct() {
+        use rand::SeedableRng;
+
+        let mut f = BloomFilter::with_fp_size(0.01, 10_000);
+        for i in 0..10_000 {
+            f.add(i);
+        }
+        assert!((0..10_000).all(|i| f.get(i)));
+        assert!(f.effective_k(&[1, 2, 3]) > f.k as f64 - 0.5);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let measured = f.measure_fp(100_000, &mut rng);
+        assert!(measured < f.current_fp() * 2.0);
+    }
+
+    #[test]
+    #[cfg(feature = "portable-hash")]
+    fn portable_hash_golden_bits() {
+        let mut f = BloomFilter::new(16, 3, 0.1);
+        for i in 0..8u32 {
+            f.add(i);
+        }
+        let set: Vec<usize> = (0..f.bits()).filter(|&i| f.array[i]).collect();
+        // captured once, must be identical on every target
+        let golden = vec![
+            2, 3, 8, 14, 21, 28, 33, 36, 37, 39, 46, 47, 53, 59, 62, 88, 97, 101, 103, 108, 118,
+            124,
+        ];
+        assert_eq!(set, golden);
+    }
+
+    #[test]
+    fn union() {
+        let mut a = BloomFilter::with_fp_size(0.1, 16);
+        a.add(&TestItem { a: 42 });
+        let mut b = BloomFilter::with_fp_size(0.1, 16);
+        b.add(&TestItem { a: 77 });
 
         a.union(&b);
         assert!(a.get(42));
@@ -368,6 +3385,174 @@ mod tests {
         assert!(!b.get(42));
     }
 
+    #[test]
+    fn union_all() {
+        let mut shards = Vec::new();
+        for shard in 0..3 {
+            let mut f = BloomFilter::with_fp_size(0.01, 100);
+            for i in 0..30 {
+                f.add(&TestItem { a: shard * 100 + i });
+            }
+            shards.push(f);
+        }
+        let u = BloomFilter::union_all(&shards).unwrap();
+        for shard in 0..3 {
+            for i in 0..30 {
+                assert!(u.get(&TestItem { a: shard * 100 + i }));
+            }
+        }
+        assert_eq!(u.stored(), 90);
+    }
+
+    #[test]
+    fn union_from_iter() {
+        let shards: Vec<BloomFilter> = (0..5)
+            .map(|shard| {
+                let mut f = BloomFilter::with_fp_size(0.01, 100);
+                for i in 0..10 {
+                    f.add(&TestItem { a: shard * 100 + i });
+                }
+                f
+            })
+            .collect();
+        let base = BloomFilter::with_fp_size(0.01, 100);
+        let u = BloomFilter::union_from_iter(base, shards.into_iter()).unwrap();
+        for shard in 0..5 {
+            for i in 0..10 {
+                assert!(u.get(&TestItem { a: shard * 100 + i }));
+            }
+        }
+        assert_eq!(u.stored(), 50);
+
+        let odd = vec![BloomFilter::with_fp_size(0.02, 100)];
+        assert_eq!(
+            BloomFilter::union_from_iter(BloomFilter::with_fp_size(0.01, 100), odd.into_iter())
+                .err(),
+            Some(BloomError::IncompatibleParameters)
+        );
+    }
+
+    #[test]
+    fn union_all_errors() {
+        assert_eq!(
+            BloomFilter::union_all(&[]).err(),
+            Some(BloomError::NoFilters)
+        );
+        let shards = [
+            BloomFilter::with_fp_size(0.01, 100),
+            BloomFilter::with_fp_size(0.01, 200),
+        ];
+        assert_eq!(
+            BloomFilter::union_all(&shards).err(),
+            Some(BloomError::IncompatibleParameters)
+        );
+    }
+
+    #[test]
+    fn is_compatible_with() {
+        let a = BloomFilter::new(64, 4, 0.01);
+        assert!(a.is_compatible_with(&BloomFilter::new(64, 4, 0.01)));
+        assert!(!a.is_compatible_with(&BloomFilter::new(32, 4, 0.01)));
+        assert!(!a.is_compatible_with(&BloomFilter::new(64, 5, 0.01)));
+        assert!(!a.is_compatible_with(&BloomFilter::new(64, 4, 0.02)));
+    }
+
+    #[test]
+    #[should_panic(expected = "same size, k and fp")]
+    fn union_panics_on_incompatible_filters() {
+        let mut a = BloomFilter::new(64, 4, 0.01);
+        a.union(&BloomFilter::new(64, 5, 0.01));
+    }
+
+    #[test]
+    fn fold() {
+        let mut f = BloomFilter::new(128, 5, 0.01);
+        for i in 0..100 {
+            f.add(&TestItem { a: i });
+        }
+        let folded = f.fold().unwrap();
+        assert_eq!(folded.bits(), f.bits() / 2);
+        assert_eq!(folded.k, f.k);
+        assert_eq!(folded.stored(), f.stored());
+        for i in 0..100 {
+            assert!(folded.get(&TestItem { a: i }));
+        }
+        assert!(folded.current_fp() > f.current_fp());
+        assert_eq!(
+            BloomFilter::new(3, 1, 0.1).fold().err(),
+            Some(BloomError::LengthMismatch)
+        );
+    }
+
+    #[test]
+    fn reductions() {
+        let mut modulo = BloomFilter::new(1000, 5, 0.01);
+        let mut shift = BloomFilter::new(1000, 5, 0.01).with_reduction(Reduction::MultiplyShift);
+        assert_eq!(modulo.reduction(), Reduction::Modulo);
+        assert!(!modulo.is_compatible_with(&shift));
+        for i in 0..200 {
+            modulo.add(i);
+            shift.add(i);
+        }
+        assert_ne!(modulo.array, shift.array);
+        for i in 0..200 {
+            assert!(modulo.get(i));
+            assert!(shift.get(i));
+        }
+
+        let folded = shift.fold().unwrap();
+        for i in 0..200 {
+            assert!(folded.get(i));
+        }
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn try_with_parameters_fails_to_allocate() {
+        assert_eq!(
+            BloomFilter::try_with_parameters(usize::MAX / 16, 3, 0.1).err(),
+            Some(BloomError::AllocFailed)
+        );
+        assert_eq!(
+            BloomFilter::try_with_parameters(usize::MAX, 3, 0.1).err(),
+            Some(BloomError::AllocFailed)
+        );
+    }
+
+    #[test]
+    fn try_constructors_errors() {
+        assert_eq!(
+            BloomFilter::try_with_parameters(0, 1, 0.1).err(),
+            Some(BloomError::ZeroSize)
+        );
+        assert_eq!(
+            BloomFilter::try_with_parameters(1, 1, 1e-300).err(),
+            Some(BloomError::ZeroCapacity)
+        );
+        assert_eq!(
+            BloomFilter::try_with_parameters(8, 1, 1.5).err(),
+            Some(BloomError::InvalidFp)
+        );
+        assert_eq!(
+            BloomFilter::try_with_fp_size(0.0, 10).err(),
+            Some(BloomError::InvalidFp)
+        );
+        assert_eq!(
+            BloomFilter::try_with_fp_size(0.1, 0).err(),
+            Some(BloomError::ZeroCapacity)
+        );
+        assert!(BloomFilter::try_with_fp_size(0.1, 10).is_ok());
+    }
+
+    #[test]
+    fn try_set_ops_errors() {
+        let mut a = BloomFilter::new(64, 4, 0.01);
+        let b = BloomFilter::new(64, 5, 0.01);
+        assert_eq!(a.try_union(&b), Err(BloomError::IncompatibleParameters));
+        assert_eq!(a.try_intersect(&b), Err(BloomError::IncompatibleParameters));
+        assert_eq!(a.try_union(&a.clone()), Ok(false));
+    }
+
     #[test]
     fn intersect() {
         let mut a = BloomFilter::with_fp_size(0.1, 16);
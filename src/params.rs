@@ -0,0 +1,71 @@
+/// Sizing of a filter before allocating it, the same calculation
+/// `BloomFilter::with_fp_size` runs.
+///
+use crate::BloomFilter;
+
+/// Parameters planned for a false probability and expected number of items
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FilterParams {
+    /// Size of storage in _bytes_
+    pub size_bytes: usize,
+    /// Total bits count, `size_bytes * 8`
+    pub bits: usize,
+    /// Number of passes for hash functions
+    pub k: usize,
+    /// fp for `expected` items with these bits and k,
+    /// (1 - e^(-k * n / m))^k, slightly off the requested one due to rounding
+    pub achieved_fp: f64,
+    /// Maximum number of items the filter holds with the requested fp
+    pub capacity: u64,
+}
+
+/// Plans size, k and capacity of a filter with false probability `fp` for
+/// `expected` items
+///
+/// # Example
+///
+/// ```
+/// let plan = bloom::params::plan(0.01, 1000);
+/// assert_eq!(plan.bits, 9592);
+/// assert_eq!(plan.k, 7);
+/// assert!(plan.achieved_fp < 0.011);
+/// ```
+pub fn plan(fp: f64, expected: u64) -> FilterParams {
+    assert!(
+        fp > 0.0 && fp < 1.0,
+        "False probability should be within (0, 1)"
+    );
+    assert!(expected != 0, "Expected number of items should be non-zero");
+    let size_bytes = BloomFilter::calculate_size_from_fp_capacity(fp, expected);
+    let bits = size_bytes * 8;
+    let k = BloomFilter::calculate_k(size_bytes, expected) as usize;
+    let achieved_fp = (1.0 - (-(k as f64) * expected as f64 / bits as f64).exp()).powi(k as i32);
+    FilterParams {
+        size_bytes,
+        bits,
+        k,
+        achieved_fp,
+        capacity: BloomFilter::calculate_capacity_from_fp_size(fp, size_bytes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn plan_matches_with_fp_size() {
+        for fp in [0.3, 0.1, 0.01, 0.0001].iter() {
+            for expected in [1, 100, 10_000].iter() {
+                let p = plan(*fp, *expected);
+                let f = BloomFilter::with_fp_size(*fp, *expected);
+                assert_eq!(p.size_bytes, f.size);
+                assert_eq!(p.bits, f.bits());
+                assert_eq!(p.k, f.k);
+                assert_eq!(p.capacity, f.capacity());
+                assert!(p.achieved_fp > 0.0 && p.achieved_fp < 1.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,159 @@
+/// Vendored SipHash-1-3 with 128-bit output, used by the `portable-hash`
+/// feature. Integers are always fed in little-endian and `usize` as `u64`,
+/// so the same item hashes to the same value on every target.
+///
+use std::hash::Hasher;
+
+#[derive(Debug, Clone)]
+pub(crate) struct SipHasher13 {
+    // total number of bytes written
+    length: usize,
+    v0: u64,
+    v1: u64,
+    v2: u64,
+    v3: u64,
+    // unprocessed bytes, little-endian
+    tail: u64,
+    // how many bytes in tail are valid
+    ntail: usize,
+}
+
+macro_rules! compress {
+    ($v0:expr, $v1:expr, $v2:expr, $v3:expr) => {{
+        $v0 = $v0.wrapping_add($v1);
+        $v1 = $v1.rotate_left(13);
+        $v1 ^= $v0;
+        $v0 = $v0.rotate_left(32);
+        $v2 = $v2.wrapping_add($v3);
+        $v3 = $v3.rotate_left(16);
+        $v3 ^= $v2;
+        $v0 = $v0.wrapping_add($v3);
+        $v3 = $v3.rotate_left(21);
+        $v3 ^= $v0;
+        $v2 = $v2.wrapping_add($v1);
+        $v1 = $v1.rotate_left(17);
+        $v1 ^= $v2;
+        $v2 = $v2.rotate_left(32);
+    }};
+}
+
+/// Loads up to 8 bytes as a little-endian integer
+#[inline]
+fn u8to64_le(buf: &[u8]) -> u64 {
+    debug_assert!(buf.len() <= 8);
+    let mut out = 0u64;
+    for (i, byte) in buf.iter().enumerate() {
+        out |= (*byte as u64) << (8 * i);
+    }
+    out
+}
+
+impl SipHasher13 {
+    /// Creates a new hasher keyed with `k0` and `k1`
+    pub(crate) fn new_with_keys(k0: u64, k1: u64) -> Self {
+        Self {
+            length: 0,
+            v0: k0 ^ 0x736f6d6570736575,
+            // 0xee marks 128-bit output
+            v1: k1 ^ 0x646f72616e646f6d ^ 0xee,
+            v2: k0 ^ 0x6c7967656e657261,
+            v3: k1 ^ 0x7465646279746573,
+            tail: 0,
+            ntail: 0,
+        }
+    }
+
+    #[inline]
+    fn c_rounds(&mut self) {
+        compress!(self.v0, self.v1, self.v2, self.v3);
+    }
+
+    #[inline]
+    fn d_rounds(&mut self) {
+        compress!(self.v0, self.v1, self.v2, self.v3);
+        compress!(self.v0, self.v1, self.v2, self.v3);
+        compress!(self.v0, self.v1, self.v2, self.v3);
+    }
+
+    /// Returns 128-bit hash of written data
+    pub(crate) fn finish128(&self) -> u128 {
+        let mut state = self.clone();
+        let b = (((self.length as u64) & 0xff) << 56) | self.tail;
+
+        state.v3 ^= b;
+        state.c_rounds();
+        state.v0 ^= b;
+
+        state.v2 ^= 0xee;
+        state.d_rounds();
+        let h1 = state.v0 ^ state.v1 ^ state.v2 ^ state.v3;
+
+        state.v1 ^= 0xdd;
+        state.d_rounds();
+        let h2 = state.v0 ^ state.v1 ^ state.v2 ^ state.v3;
+
+        (h2 as u128) << 64 | h1 as u128
+    }
+}
+
+impl Hasher for SipHasher13 {
+    fn write(&mut self, msg: &[u8]) {
+        let length = msg.len();
+        self.length += length;
+
+        let mut needed = 0;
+        if self.ntail != 0 {
+            needed = 8 - self.ntail;
+            let fill = length.min(needed);
+            self.tail |= u8to64_le(&msg[..fill]) << (8 * self.ntail);
+            if length < needed {
+                self.ntail += length;
+                return;
+            }
+            let m = self.tail;
+            self.v3 ^= m;
+            self.c_rounds();
+            self.v0 ^= m;
+            self.ntail = 0;
+        }
+
+        let len = length - needed;
+        let left = len & 0x7;
+        let mut i = needed;
+        while i < needed + len - left {
+            let m = u8to64_le(&msg[i..i + 8]);
+            self.v3 ^= m;
+            self.c_rounds();
+            self.v0 ^= m;
+            i += 8;
+        }
+
+        self.tail = u8to64_le(&msg[i..]);
+        self.ntail = left;
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        // usize width differs between targets
+        self.write_u64(i as u64)
+    }
+
+    fn finish(&self) -> u64 {
+        self.finish128() as u64
+    }
+}
@@ -0,0 +1,139 @@
+/// Sparse form of `BloomFilter` keeping only the indices of set bits, which
+/// is much smaller than the full bit array while the filter is mostly empty
+/// (below ~1/64 fill ratio, as every index takes 8 bytes).
+///
+use crate::{BloomFilter, Hash, Reduction};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SparseBloom {
+    // size of the dense filter in _bytes_
+    size: usize,
+    // Number of passes for hash functions
+    k: usize,
+    // False probability rate of the dense filter
+    fp: f64,
+    // stored number of items
+    stored_items: u64,
+    // seeds mixed into hashes
+    seeds: [u64; 2],
+    // how hashes are mapped to bit indices
+    reduction: Reduction,
+    // sorted indices of set bits
+    indices: Vec<u64>,
+}
+
+impl SparseBloom {
+    /// Returns number of currently stored items
+    pub fn stored(&self) -> u64 {
+        self.stored_items
+    }
+
+    /// Returns number of set bits
+    pub fn set_bits(&self) -> usize {
+        self.indices.len()
+    }
+
+    /// Returns number of bytes taken by set bit indices
+    pub fn heap_bytes(&self) -> usize {
+        self.indices.len() * std::mem::size_of::<u64>()
+    }
+
+    /// Checks that item is in filter, looking up every bit with binary search
+    pub fn get<I: Hash>(&self, item: I) -> bool {
+        let base = BloomFilter::base_hashes(&item, self.seeds);
+        let bits = (self.size * 8) as u128;
+        (0..self.k).all(|i| {
+            let idx = self.reduction.position(&base, i, bits) as u64;
+            self.indices.binary_search(&idx).is_ok()
+        })
+    }
+}
+
+impl BloomFilter {
+    /// Exports the filter as a sorted list of set bit indices, or `None` if
+    /// the indices wouldn't take less space than the bit array, i.e. once
+    /// more than 1/64 of the bits are set
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut f = bloom::BloomFilter::with_fp_size(0.01, 1000);
+    /// f.add(&42);
+    /// let sparse = f.to_sparse().unwrap();
+    /// assert!(sparse.get(&42));
+    /// assert!(sparse.heap_bytes() < f.bits() / 8);
+    /// ```
+    pub fn to_sparse(&self) -> Option<SparseBloom> {
+        let index_bits = 8 * std::mem::size_of::<u64>();
+        if self.set_bits() * index_bits >= self.bits() {
+            return None;
+        }
+        let indices = self
+            .array
+            .iter()
+            .enumerate()
+            .filter(|(_, bit)| *bit)
+            .map(|(idx, _)| idx as u64)
+            .collect();
+        Some(SparseBloom {
+            size: self.size,
+            k: self.k,
+            fp: self.fp,
+            stored_items: self.stored_items,
+            seeds: self.seeds,
+            reduction: self.reduction,
+            indices,
+        })
+    }
+
+    /// Restores a dense filter from its sparse form
+    pub fn from_sparse(sparse: &SparseBloom) -> Self {
+        let mut f = BloomFilter::with_seeds(sparse.size, sparse.k, sparse.fp, sparse.seeds)
+            .with_reduction(sparse.reduction);
+        for idx in sparse.indices.iter() {
+            f.array.set(*idx as usize, true);
+        }
+        f.stored_items = sparse.stored_items;
+        f
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn sparse_round_trip() {
+        let mut f = BloomFilter::with_fp_size(0.01, 10_000);
+        for i in 0..50 {
+            f.add(i);
+        }
+        let sparse = f.to_sparse().unwrap();
+        assert_eq!(sparse.stored(), 50);
+        assert_eq!(sparse.set_bits(), f.set_bits());
+        assert!(sparse.heap_bytes() * 2 < f.bits() / 8);
+        for i in 0..50 {
+            assert!(sparse.get(i));
+        }
+        for i in 1000..1100 {
+            assert_eq!(sparse.get(i), f.get(i));
+        }
+
+        let restored = BloomFilter::from_sparse(&sparse);
+        assert_eq!(restored.array, f.array);
+        assert_eq!(restored.stored(), 50);
+        assert!(restored.is_compatible_with(&f));
+    }
+
+    #[test]
+    fn dense_filter_has_no_sparse_form() {
+        let mut f = BloomFilter::with_fp_size(0.01, 1000);
+        assert_eq!(f.to_sparse().unwrap().set_bits(), 0);
+        for i in 0..1000 {
+            f.add(i);
+        }
+        assert!(f.set_bits() * 64 > f.bits());
+        assert_eq!(f.to_sparse(), None);
+    }
+}